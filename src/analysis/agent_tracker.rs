@@ -0,0 +1,325 @@
+use tracing::debug;
+
+use crate::{ring_buffer::RingBuffer, Args};
+
+use super::{
+    beat_tracker::{BeatHypothesis, BeatTracker},
+    bpm_tracker::Bpm,
+    tempogram::TempoEstimate,
+};
+
+/// One hypothesis of the tempo and phase of the beat, which predicts the next beat and is rated by
+/// how well the onsets match its predictions.
+#[derive(Clone)]
+struct Agent {
+    id: u64,
+    /// Beat period in samples.
+    period: f64,
+    /// Sample position of the most recent beat, matched to an onset or predicted.
+    beat_sample: f64,
+    /// Sum of the ratings of the past beats, forgotten exponentially per beat.
+    score: f32,
+    /// Mean of the squared offsets of the matched onsets, in half beats.
+    error: f32,
+    /// Beats since the last matched onset.
+    misses: u32,
+}
+
+impl Agent {
+    fn bpm(&self, sample_rate: f32) -> f32 {
+        60.0 * sample_rate / self.period as f32
+    }
+
+    /// Offset of the grid relative to sample 0 in seconds, in `[0, period)`.
+    fn phase(&self, sample_rate: f32) -> f32 {
+        let period_s = self.period / sample_rate as f64;
+        (self.beat_sample / sample_rate as f64).rem_euclid(period_s) as f32
+    }
+
+    /// Distance of `sample` to the closest beat of the agent, in beats.
+    fn offset(&self, sample: f64) -> f64 {
+        let beats = (sample - self.beat_sample) / self.period;
+        beats - beats.round()
+    }
+
+    /// Follow the beat up to the onset at `sample`, and towards it if it is on the beat.
+    fn on_onset(&mut self, sample: f64, min_period: f64, max_period: f64) {
+        let tolerance = AgentTracker::TOLERANCE * self.period;
+
+        // Beats which passed without an onset.
+        while sample > self.beat_sample + self.period + tolerance {
+            self.beat_sample += self.period;
+            self.score = AgentTracker::SCORE_DECAY * self.score - AgentTracker::MISS_PENALTY;
+            self.misses += 1;
+        }
+
+        let predicted = self.beat_sample + self.period;
+        let offset = sample - predicted;
+        if offset.abs() > tolerance {
+            // Between two beats, e.g. an off-beat hi-hat.
+            return;
+        }
+
+        let offset_beats = (offset / self.period) as f32;
+        let closeness = 1.0 - offset_beats.abs() / AgentTracker::TOLERANCE as f32;
+        self.score = AgentTracker::SCORE_DECAY * self.score + 0.5 + 0.5 * closeness;
+        self.error = AgentTracker::ERROR_DECAY * self.error
+            + (1.0 - AgentTracker::ERROR_DECAY) * (2.0 * offset_beats).powi(2);
+        self.misses = 0;
+
+        // Move part of the way towards the onset, a single early or late onset shouldn't move
+        // the grid much.
+        self.period =
+            (self.period + AgentTracker::PERIOD_CORRECTION * offset).clamp(min_period, max_period);
+        self.beat_sample = predicted + AgentTracker::PHASE_CORRECTION * offset;
+    }
+}
+
+/// Beat tracking with a population of agents, similar to BeatRoot.
+///
+/// Every agent follows its own tempo and phase, new agents are started from the intervals between
+/// recent onsets and from the autocorrelation tempo. All agents are rated continuously against the
+/// onsets, the grid follows the best one until another one is rated clearly better. Transitions
+/// are smooth that way: agents at the new tempo build up their ratings while the old ones decay.
+pub struct AgentTracker {
+    sample_rate: f32,
+    min_period: f64,
+    max_period: f64,
+
+    agents: Vec<Agent>,
+    next_id: u64,
+    best_id: Option<u64>,
+    onsets: RingBuffer<u64>,
+    tempo_estimate: Option<TempoEstimate>,
+}
+
+impl AgentTracker {
+    /// Maximum distance of an onset to a predicted beat, in beats.
+    const TOLERANCE: f64 = 0.15;
+    const PERIOD_CORRECTION: f64 = 0.1;
+    const PHASE_CORRECTION: f64 = 0.5;
+    /// Roughly the last ten beats contribute to the score.
+    const SCORE_DECAY: f32 = 0.9;
+    const MISS_PENALTY: f32 = 0.5;
+    const ERROR_DECAY: f32 = 0.9;
+    /// Scales the mean squared error to the sum over the 15 beats of the `BpmTracker`.
+    const ERROR_SCALE: f32 = 15.0;
+    /// Agents which didn't match an onset for this many beats are dropped.
+    const MAX_MISSES: u32 = 8;
+    const MAX_AGENTS: usize = 32;
+    /// Onsets whose intervals to the latest one start new agents.
+    const ONSET_HISTORY: usize = 6;
+    /// Agents closer than this in tempo (relative) and phase (in beats) are merged.
+    const SAME_TEMPO: f64 = 0.02;
+    const SAME_PHASE: f64 = 0.05;
+    /// Another agent has to be rated this much better to be followed instead.
+    const SWITCH_MARGIN: f32 = 1.2;
+    /// Relative tempo deviation up to which a candidate of the autocorrelation supports an agent.
+    const CANDIDATE_RANGE: f32 = 0.04;
+    /// Weight of agents without support, so that they are rated before the first estimate.
+    const MIN_SUPPORT: f32 = 0.1;
+    const NUM_HYPOTHESES: usize = 5;
+
+    pub fn new(args: &Args, sample_rate: f32) -> Self {
+        let period_of_bpm = |bpm: u32| 60.0 * sample_rate as f64 / bpm as f64;
+        Self {
+            sample_rate,
+            min_period: period_of_bpm(args.fastest_bpm),
+            max_period: period_of_bpm(args.slowest_bpm),
+
+            agents: Vec::with_capacity(Self::MAX_AGENTS),
+            next_id: 0,
+            best_id: None,
+            onsets: RingBuffer::new(Self::ONSET_HISTORY),
+            tempo_estimate: None,
+        }
+    }
+
+    /// Start an agent unless an equivalent one exists already.
+    fn spawn(&mut self, period: f64, beat_sample: f64) {
+        if !(self.min_period..=self.max_period).contains(&period) {
+            return;
+        }
+        let exists = self.agents.iter().any(|agent| {
+            (agent.period / period - 1.0).abs() < Self::SAME_TEMPO
+                && agent.offset(beat_sample).abs() < Self::TOLERANCE
+        });
+        if exists {
+            return;
+        }
+
+        self.agents.push(Agent {
+            id: self.next_id,
+            period,
+            beat_sample,
+            score: 0.0,
+            error: 0.0,
+            misses: 0,
+        });
+        self.next_id += 1;
+    }
+
+    /// The score, weighted with the support of the autocorrelation for the agent's tempo. Onsets
+    /// between the beats fit faster tempi just as well, the autocorrelation takes care of octaves.
+    fn rating(&self, agent: &Agent) -> f32 {
+        let bpm = agent.bpm(self.sample_rate);
+        let support = self
+            .tempo_estimate
+            .iter()
+            .flat_map(|estimate| estimate.candidates.iter())
+            .filter(|candidate| (candidate.bpm / bpm - 1.0).abs() < Self::CANDIDATE_RANGE)
+            .map(|candidate| candidate.score)
+            .fold(0.0, f32::max);
+        agent.score * (Self::MIN_SUPPORT + support)
+    }
+
+    /// Drop lost and duplicate agents, keep the best rated ones.
+    fn prune(&mut self) {
+        self.agents.retain(|agent| agent.misses <= Self::MAX_MISSES);
+
+        let mut ratings = self
+            .agents
+            .iter()
+            .map(|agent| (self.rating(agent), agent.clone()))
+            .collect::<Vec<_>>();
+        ratings.sort_unstable_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+        let mut kept: Vec<Agent> = Vec::with_capacity(Self::MAX_AGENTS);
+        for (_, agent) in ratings {
+            let is_duplicate = kept.iter().any(|other| {
+                (other.period / agent.period - 1.0).abs() < Self::SAME_TEMPO
+                    && other.offset(agent.beat_sample).abs() < Self::SAME_PHASE
+            });
+            if !is_duplicate && kept.len() < Self::MAX_AGENTS {
+                kept.push(agent);
+            }
+        }
+        self.agents = kept;
+    }
+
+    fn select_best(&mut self) {
+        let Some(best) = self.agents.first() else {
+            self.best_id = None;
+            return;
+        };
+        let best_rating = self.rating(best);
+
+        let current = self
+            .best_id
+            .and_then(|id| self.agents.iter().find(|agent| agent.id == id));
+        if let Some(current) = current {
+            if best_rating <= Self::SWITCH_MARGIN * self.rating(current) {
+                return;
+            }
+        }
+
+        if best_rating > 0.0 && self.best_id != Some(best.id) {
+            debug!(
+                "Following the agent at {:.1} BPM",
+                best.bpm(self.sample_rate)
+            );
+            self.best_id = Some(best.id);
+        }
+    }
+
+    fn best(&self) -> Option<&Agent> {
+        let id = self.best_id?;
+        self.agents.iter().find(|agent| agent.id == id)
+    }
+}
+
+impl BeatTracker for AgentTracker {
+    fn on_beat(&mut self, sample_index: u64) {
+        let sample = sample_index as f64;
+        for agent in self.agents.iter_mut() {
+            agent.on_onset(sample, self.min_period, self.max_period);
+        }
+
+        for index in 0..self.onsets.size {
+            let onset = self.onsets.data[index];
+            if onset > 0 {
+                self.spawn(sample - onset as f64, sample);
+            }
+        }
+        self.onsets.push(sample_index);
+
+        // Sorts the agents by rating as well.
+        self.prune();
+        self.select_best();
+    }
+
+    fn on_tempo_estimate(&mut self, estimate: TempoEstimate) {
+        let period = 60.0 * self.sample_rate as f64 / estimate.bpm as f64;
+        self.spawn(period, estimate.beat_sample as f64);
+        self.tempo_estimate = Some(estimate);
+    }
+
+    /// Replaces all agents by one on the new grid, rated as if it had matched every recent beat.
+    fn set_grid(&mut self, bpm: f32, beat_sample: u64) {
+        let period =
+            (60.0 * self.sample_rate as f64 / bpm as f64).clamp(self.min_period, self.max_period);
+        self.agents.clear();
+        self.onsets = RingBuffer::new(Self::ONSET_HISTORY);
+        self.agents.push(Agent {
+            id: self.next_id,
+            period,
+            beat_sample: beat_sample as f64,
+            score: 1.0 / (1.0 - Self::SCORE_DECAY),
+            error: 0.0,
+            misses: 0,
+        });
+        self.best_id = Some(self.next_id);
+        self.next_id += 1;
+    }
+
+    fn set_beats_per_bar(&mut self, _beats_per_bar: u32) {}
+
+    fn bpm(&self) -> Bpm {
+        match self.best() {
+            Some(agent) => Bpm::new(agent.bpm(self.sample_rate)),
+            None => Bpm::new(120.0),
+        }
+    }
+
+    fn phase_offset(&self) -> f32 {
+        self.best()
+            .map_or(0.0, |agent| agent.phase(self.sample_rate))
+    }
+
+    fn sample_to_beat_fract(&self, sample_index: u64) -> f32 {
+        self.best().map_or(0.0, |agent| {
+            let beats = (sample_index as f64 - agent.beat_sample) / agent.period;
+            beats.rem_euclid(1.0) as f32
+        })
+    }
+
+    fn phase_error(&self) -> f32 {
+        self.best()
+            .map_or(0.0, |agent| Self::ERROR_SCALE * agent.error)
+    }
+
+    /// The share of recent beats which were matched by an onset.
+    fn bpm_confidence(&self) -> f32 {
+        self.best().map_or(0.0, |agent| {
+            (agent.score * (1.0 - Self::SCORE_DECAY)).clamp(0.0, 1.0)
+        })
+    }
+
+    fn hypotheses(&self) -> Vec<BeatHypothesis> {
+        let to_hypothesis = |agent: &Agent| BeatHypothesis {
+            bpm: agent.bpm(self.sample_rate),
+            phase: agent.phase(self.sample_rate),
+            score: self.rating(agent),
+        };
+        let best = self.best().map(to_hypothesis);
+        let others = self
+            .agents
+            .iter()
+            .filter(|agent| Some(agent.id) != self.best_id)
+            .map(to_hypothesis);
+        best.into_iter()
+            .chain(others)
+            .take(Self::NUM_HYPOTHESES)
+            .collect()
+    }
+}
@@ -0,0 +1,316 @@
+use tracing::debug;
+
+use crate::{
+    filters::{alpha_avg::AlphaAvg, filter::Filter},
+    ring_buffer::RingBuffer,
+    Args,
+};
+
+/// Beats per bar. Beats are the pulse found by the `BpmTracker`, which is assumed to be the
+/// notated beat unit, i.e. eighths for 6/8 and 7/8.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Meter {
+    /// Detect the number of beats per bar from the downbeat ratings
+    Auto,
+    #[value(name = "3/4")]
+    ThreeFour,
+    #[value(name = "4/4")]
+    FourFour,
+    #[value(name = "6/8")]
+    SixEight,
+    #[value(name = "7/8")]
+    SevenEight,
+}
+
+impl Meter {
+    const DETECTABLE: [Meter; 4] = [
+        Meter::ThreeFour,
+        Meter::FourFour,
+        Meter::SixEight,
+        Meter::SevenEight,
+    ];
+
+    fn beats_per_bar(self) -> Option<u32> {
+        match self {
+            Meter::Auto => None,
+            Meter::ThreeFour => Some(3),
+            Meter::FourFour => Some(4),
+            Meter::SixEight => Some(6),
+            Meter::SevenEight => Some(7),
+        }
+    }
+}
+
+/// Position within a phrase of `length` bars.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Phrase {
+    pub length: u32,
+    pub bar: u32,
+    pub confidence: f32,
+}
+
+/// Finds bar and phrase boundaries on top of the beat grid of the `BpmTracker`.
+///
+/// Every beat is rated by how much the spectrum changed since the previous beat and by its bass
+/// energy. The ratings are accumulated per position in the bar, the position with the highest
+/// rating is the downbeat. Phrases are found the same way on bar level.
+pub struct BarTracker {
+    meter: Meter,
+    beat_count: u32,
+
+    beat_spectrum: Vec<f32>,
+    beat_spectrum_ticks: u32,
+    previous_beat_spectrum: Vec<f32>,
+    beat_bass_energy: f32,
+
+    change_avg: AlphaAvg,
+    bass_avg: AlphaAvg,
+    ratings: RingBuffer<f32>,
+    downbeat_offset: u32,
+
+    bar_spectrum: Vec<f32>,
+    previous_bar_spectrum: Vec<f32>,
+    bar_change_avg: AlphaAvg,
+    phrase_scores: [f32; Self::MAX_PHRASE_BARS as usize],
+
+    pub beats_per_bar: u32,
+    pub beat_in_bar: u32,
+    pub bar_index: u32,
+    pub downbeat_confidence: f32,
+    pub phrases: [Phrase; 3],
+}
+
+impl BarTracker {
+    /// Used until a meter has been detected.
+    const DEFAULT_BEATS_PER_BAR: u32 = 4;
+    /// Enough beats for a few bars of every detectable meter.
+    const RATING_HISTORY: usize = 168;
+    /// The shortest bar within this fraction of the most salient one is chosen, 3/4 has salient
+    /// downbeats when folded into six beats as well.
+    const METER_TOLERANCE: f32 = 0.9;
+    /// Below this, the bars are too ambiguous to switch the meter.
+    const MIN_METER_SALIENCE: f32 = 0.05;
+    const MAX_PHRASE_BARS: u32 = 32;
+    const PHRASE_LENGTHS: [u32; 3] = [8, 16, 32];
+
+    /// Decay of the accumulated rating of a bar position, applied once per bar.
+    const DOWNBEAT_DECAY: f32 = 0.9;
+    /// Decay of the accumulated rating of a phrase position, applied once per 32 bars.
+    const PHRASE_DECAY: f32 = 0.7;
+    const BASS_WEIGHT: f32 = 0.5;
+
+    pub fn new(args: &Args, num_bins: usize) -> Self {
+        Self {
+            meter: args.meter,
+            beat_count: 0,
+
+            beat_spectrum: vec![0.0; num_bins],
+            beat_spectrum_ticks: 0,
+            previous_beat_spectrum: vec![0.0; num_bins],
+            beat_bass_energy: 0.0,
+
+            change_avg: AlphaAvg::new(0.99),
+            bass_avg: AlphaAvg::new(0.99),
+            ratings: RingBuffer::new(Self::RATING_HISTORY),
+            downbeat_offset: 0,
+
+            bar_spectrum: vec![0.0; num_bins],
+            previous_bar_spectrum: vec![0.0; num_bins],
+            bar_change_avg: AlphaAvg::new(0.95),
+            phrase_scores: [0.0; Self::MAX_PHRASE_BARS as usize],
+
+            beats_per_bar: args
+                .meter
+                .beats_per_bar()
+                .unwrap_or(Self::DEFAULT_BEATS_PER_BAR),
+            beat_in_bar: 0,
+            bar_index: 0,
+            downbeat_confidence: 0.0,
+            phrases: Self::PHRASE_LENGTHS.map(|length| Phrase {
+                length,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn on_pcm_sample(&mut self, bass_energy: f32) {
+        self.beat_bass_energy += bass_energy;
+    }
+
+    /// Accumulate the spectrum of the current tick.
+    pub fn on_tick(&mut self, spectrum: impl Iterator<Item = f32>) {
+        for (sum, value) in self.beat_spectrum.iter_mut().zip(spectrum) {
+            *sum += value;
+        }
+        self.beat_spectrum_ticks += 1;
+    }
+
+    /// Mean absolute difference, in dB.
+    fn spectral_change(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum::<f32>() / a.len() as f32
+    }
+
+    /// Index of the best entry and how much it stands out from the second best, in `[0, 1]`.
+    fn best_with_confidence(scores: impl Iterator<Item = f32>) -> (usize, f32) {
+        let (mut best, mut first, mut second) = (0, 0.0, 0.0);
+        for (index, score) in scores.enumerate() {
+            if score > first {
+                (best, first, second) = (index, score, first);
+            } else if score > second {
+                second = score;
+            }
+        }
+        let confidence = if first > 0.0 {
+            1.0 - second / first
+        } else {
+            0.0
+        };
+        (best, confidence)
+    }
+
+    /// Call once per beat of the grid.
+    pub fn on_beat(&mut self) {
+        let ticks = self.beat_spectrum_ticks.max(1) as f32;
+        for value in self.beat_spectrum.iter_mut() {
+            *value /= ticks;
+        }
+
+        // Rate this beat as a downbeat candidate, relative to the recent beats.
+        let change = Self::spectral_change(&self.beat_spectrum, &self.previous_beat_spectrum);
+        let change_avg = self.change_avg.sample(change).max(f32::EPSILON);
+        let bass_avg = self
+            .bass_avg
+            .sample(self.beat_bass_energy)
+            .max(f32::EPSILON);
+        let rating = change / change_avg + Self::BASS_WEIGHT * self.beat_bass_energy / bass_avg;
+
+        self.ratings.push(rating);
+        self.beat_count += 1;
+
+        let (offset, confidence) = Self::best_with_confidence(self.downbeat_scores());
+        self.downbeat_offset = offset as u32;
+        self.downbeat_confidence = confidence;
+
+        for (bar, beat) in self.bar_spectrum.iter_mut().zip(self.beat_spectrum.iter()) {
+            *bar += beat;
+        }
+        std::mem::swap(&mut self.beat_spectrum, &mut self.previous_beat_spectrum);
+        self.beat_spectrum.fill(0.0);
+        self.beat_spectrum_ticks = 0;
+        self.beat_bass_energy = 0.0;
+
+        let beat_in_bar =
+            (self.beat_count + self.beats_per_bar - self.downbeat_offset) % self.beats_per_bar;
+        if beat_in_bar == 0 || beat_in_bar < self.beat_in_bar {
+            self.on_bar();
+        }
+        self.beat_in_bar = beat_in_bar;
+    }
+
+    /// The ratings of the recent beats, newest first, along with their beat index.
+    fn recent_ratings(&self) -> impl Iterator<Item = (u32, f32)> + '_ {
+        let size = self.ratings.size;
+        let newest = self.ratings.prev_index + size;
+        (0..(self.beat_count as usize).min(size)).map(move |age| {
+            let rating = self.ratings.data[(newest - age) % size];
+            (self.beat_count - 1 - age as u32, rating)
+        })
+    }
+
+    /// Decayed sum of the ratings per position in the bar.
+    fn downbeat_scores(&self) -> impl Iterator<Item = f32> {
+        let mut scores = vec![0.0; self.beats_per_bar as usize];
+        let newest_bar = self.beat_count / self.beats_per_bar;
+        for (beat, rating) in self.recent_ratings() {
+            let bars_ago = newest_bar - beat / self.beats_per_bar;
+            scores[(beat % self.beats_per_bar) as usize] +=
+                Self::DOWNBEAT_DECAY.powi(bars_ago as i32) * rating;
+        }
+        scores.into_iter()
+    }
+
+    /// How much the ratings vary between the positions when folding them into bars of `beats`,
+    /// relative to their overall variance. The variance that noise alone would cause is
+    /// subtracted, otherwise longer bars with fewer ratings per position would be preferred.
+    fn bar_salience(&self, beats: u32) -> f32 {
+        let ratings = self.recent_ratings().collect::<Vec<_>>();
+        let count = ratings.len() as f32;
+        let mean = ratings.iter().map(|(_, rating)| rating).sum::<f32>() / count;
+        let variance = ratings
+            .iter()
+            .map(|(_, rating)| (rating - mean).powi(2))
+            .sum::<f32>()
+            / count;
+
+        let mut sums = vec![(0.0, 0); beats as usize];
+        for (beat, rating) in ratings {
+            let (sum, n) = &mut sums[(beat % beats) as usize];
+            *sum += rating;
+            *n += 1;
+        }
+        let between = sums
+            .iter()
+            .map(|(sum, n)| (sum / (*n).max(1) as f32 - mean).powi(2))
+            .sum::<f32>()
+            / beats as f32;
+        let noise = variance * beats as f32 / count;
+        (between - noise) / variance.max(f32::EPSILON)
+    }
+
+    fn detect_meter(&mut self) {
+        if self.meter != Meter::Auto || (self.beat_count as usize) < Self::RATING_HISTORY {
+            return;
+        }
+
+        let saliences = Meter::DETECTABLE.map(|meter| {
+            let beats_per_bar = meter.beats_per_bar().unwrap();
+            (beats_per_bar, self.bar_salience(beats_per_bar))
+        });
+        let best = saliences
+            .iter()
+            .map(|(_, salience)| *salience)
+            .fold(f32::MIN, f32::max);
+        if best < Self::MIN_METER_SALIENCE {
+            return;
+        }
+        let (beats_per_bar, _) = saliences
+            .into_iter()
+            .find(|(_, salience)| *salience >= Self::METER_TOLERANCE * best)
+            .unwrap();
+
+        if beats_per_bar != self.beats_per_bar {
+            debug!("Switched to {beats_per_bar} beats per bar");
+            self.beats_per_bar = beats_per_bar;
+        }
+    }
+
+    fn on_bar(&mut self) {
+        self.detect_meter();
+
+        // Phrases start where the sound changes the most between two bars.
+        let change = Self::spectral_change(&self.bar_spectrum, &self.previous_bar_spectrum);
+        let change_avg = self.bar_change_avg.sample(change).max(f32::EPSILON);
+
+        let position = (self.bar_index % Self::MAX_PHRASE_BARS) as usize;
+        let score = &mut self.phrase_scores[position];
+        *score = Self::PHRASE_DECAY * *score + change / change_avg;
+        self.bar_index += 1;
+
+        for phrase in self.phrases.iter_mut() {
+            let length = phrase.length as usize;
+            let folded = (0..length).map(|offset| {
+                self.phrase_scores
+                    .iter()
+                    .skip(offset)
+                    .step_by(length)
+                    .sum::<f32>()
+            });
+            let (offset, confidence) = Self::best_with_confidence(folded);
+            phrase.bar = (self.bar_index + phrase.length - offset as u32) % phrase.length;
+            phrase.confidence = confidence;
+        }
+
+        std::mem::swap(&mut self.bar_spectrum, &mut self.previous_bar_spectrum);
+        self.bar_spectrum.fill(0.0);
+    }
+}
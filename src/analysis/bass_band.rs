@@ -0,0 +1,147 @@
+use tracing::debug;
+
+use crate::{
+    filters::{biquad_band_pass::BiquadBandPass, filter::Filter},
+    ring_buffer::RingBuffer,
+    Args,
+};
+
+struct CandidateBand {
+    center_fq: usize,
+    filter: BiquadBandPass,
+    output: f32,
+    hop_energy: f32,
+    previous: f32,
+    /// Rectified increase of the band energy per hop.
+    envelope: RingBuffer<f32>,
+}
+
+/// Band-pass filter which follows the kick. A bank of band-passes runs in parallel, every few
+/// seconds the band whose onset envelope is the most periodic within the BPM range is selected.
+pub struct BandSelector {
+    bands: Vec<CandidateBand>,
+    selected: usize,
+    sample_count: u64,
+    hops_since_evaluation: usize,
+    hops_per_evaluation: usize,
+    min_lag: usize,
+    max_lag: usize,
+}
+
+impl BandSelector {
+    /// Center frequencies of the candidate bands, from sub-thumps to punchy kicks.
+    const CANDIDATE_FQS: [usize; 6] = [40, 50, 63, 80, 100, 125];
+    const HOP_SIZE: u64 = 256;
+    const WINDOW_S: f32 = 6.0;
+    const EVALUATE_S: f32 = 2.0;
+    /// A different band has to be this much more periodic than the selected one.
+    const SWITCH_MARGIN: f32 = 1.1;
+
+    pub fn new(args: &Args, sample_rate: f32, q: f32) -> Self {
+        let hops_per_s = sample_rate / Self::HOP_SIZE as f32;
+        let window = (hops_per_s * Self::WINDOW_S) as usize;
+        let lag_of_bpm = |bpm: u32| hops_per_s * 60.0 / bpm as f32;
+
+        let bands = Self::CANDIDATE_FQS
+            .iter()
+            .map(|&center_fq| CandidateBand {
+                center_fq,
+                filter: BiquadBandPass::new(sample_rate, center_fq, q),
+                output: 0.0,
+                hop_energy: 0.0,
+                previous: 0.0,
+                envelope: RingBuffer::new(window),
+            })
+            .collect::<Vec<_>>();
+        let selected = bands
+            .iter()
+            .position(|band| band.center_fq == 50)
+            .unwrap_or_default();
+
+        Self {
+            bands,
+            selected,
+            sample_count: 0,
+            hops_since_evaluation: 0,
+            hops_per_evaluation: (hops_per_s * Self::EVALUATE_S) as usize,
+            min_lag: (lag_of_bpm(args.fastest_bpm).floor() as usize).max(1),
+            max_lag: (lag_of_bpm(args.slowest_bpm).ceil() as usize).min(window - 1),
+        }
+    }
+
+    fn on_hop(&mut self) {
+        for band in self.bands.iter_mut() {
+            let energy = band.hop_energy / Self::HOP_SIZE as f32;
+            band.envelope.push((energy - band.previous).max(0.0));
+            band.previous = energy;
+            band.hop_energy = 0.0;
+        }
+
+        self.hops_since_evaluation += 1;
+        if self.hops_since_evaluation < self.hops_per_evaluation {
+            return;
+        }
+        self.hops_since_evaluation = 0;
+
+        let periodicities = self
+            .bands
+            .iter()
+            .map(|band| self.periodicity(&band.envelope))
+            .collect::<Vec<_>>();
+        let (best, best_periodicity) = periodicities
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap();
+        if *best_periodicity > Self::SWITCH_MARGIN * periodicities[self.selected] {
+            debug!(
+                "Switched bass band to {} Hz, periodicity {best_periodicity:.2}",
+                self.bands[best].center_fq
+            );
+            self.selected = best;
+        }
+    }
+
+    /// Highest normalized autocorrelation of the envelope within the lags of the BPM range.
+    fn periodicity(&self, envelope: &RingBuffer<f32>) -> f32 {
+        let size = envelope.size;
+        let oldest = envelope.write_index;
+        let values = (0..size)
+            .map(|index| envelope.data[(oldest + index) % size])
+            .collect::<Vec<_>>();
+        let mean = values.iter().sum::<f32>() / size as f32;
+        let values = values.iter().map(|x| x - mean).collect::<Vec<_>>();
+
+        let energy = values.iter().map(|x| x * x).sum::<f32>();
+        if energy <= f32::EPSILON {
+            return 0.0;
+        }
+        (self.min_lag..=self.max_lag)
+            .map(|lag| {
+                values
+                    .iter()
+                    .zip(values[lag..].iter())
+                    .map(|(a, b)| a * b)
+                    .sum::<f32>()
+            })
+            .fold(0.0, f32::max)
+            / energy
+    }
+}
+
+impl Filter for BandSelector {
+    /// Runs all candidate bands and returns the output of the selected one.
+    fn sample(&mut self, x: f32) -> f32 {
+        for band in self.bands.iter_mut() {
+            band.output = band.filter.sample(x);
+            band.hop_energy += band.output * band.output;
+        }
+        let output = self.bands[self.selected].output;
+
+        self.sample_count += 1;
+        if self.sample_count % Self::HOP_SIZE == 0 {
+            self.on_hop();
+        }
+        output
+    }
+}
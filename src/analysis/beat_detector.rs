@@ -10,6 +10,8 @@ use crate::{
     Args,
 };
 
+use super::{bass_band::BandSelector, onset_detector::OnsetDetector};
+
 pub struct BeatStats {
     pub frames_since_last_beat: u32,
     pub min_frames_threshold: u32,
@@ -77,8 +79,38 @@ impl BeatStats {
     }
 }
 
+/// The frequency band a `BeatDetector` listens to.
+pub struct Band {
+    pub center_fq: usize,
+    pub q: f32,
+    pub energy_window_s: f32,
+    /// How many onsets per beat (at the fastest BPM) can be registered at most.
+    pub onsets_per_beat: f32,
+}
+
+impl Band {
+    pub const KICK: Band = Band {
+        center_fq: 50,
+        q: 6.0,
+        energy_window_s: 0.1,
+        onsets_per_beat: 1.0,
+    };
+    pub const SNARE: Band = Band {
+        center_fq: 1000,
+        q: 0.5,
+        energy_window_s: 0.05,
+        onsets_per_beat: 1.0,
+    };
+    pub const HIHAT: Band = Band {
+        center_fq: 9000,
+        q: 0.7,
+        energy_window_s: 0.02,
+        onsets_per_beat: 4.0,
+    };
+}
+
 pub struct BeatDetector {
-    pub filter: BiquadBandPass,
+    pub filter: Box<dyn Filter>,
     pub energy: Energy,
     pub stats: BeatStats,
 }
@@ -86,16 +118,35 @@ pub struct BeatDetector {
 impl BeatDetector {
     const BEAT_FRAMES_PER_SAMPLE: f32 = 64.0;
 
+    /// Listens to the kick band, either pinned from the command line or selected adaptively.
     pub fn new(args: &Args, sample_rate: f32) -> Self {
+        let q = args.bass_q.unwrap_or(Band::KICK.q);
+        let filter: Box<dyn Filter> = match args.bass_fq {
+            Some(center_fq) => Box::new(BiquadBandPass::new(sample_rate, center_fq, q)),
+            None => Box::new(BandSelector::new(args, sample_rate, q)),
+        };
+        Self::with_filter(args, sample_rate, filter, &Band::KICK)
+    }
+
+    pub fn with_band(args: &Args, sample_rate: f32, band: &Band) -> Self {
+        let filter = Box::new(BiquadBandPass::new(sample_rate, band.center_fq, band.q));
+        Self::with_filter(args, sample_rate, filter, band)
+    }
+
+    /// `band` only determines the energy window and the onset rate, `filter` selects the band.
+    fn with_filter(args: &Args, sample_rate: f32, filter: Box<dyn Filter>, band: &Band) -> Self {
         let beat_frames_per_s = sample_rate / Self::BEAT_FRAMES_PER_SAMPLE;
+        let fastest_onsets_per_min = band.onsets_per_beat * args.fastest_bpm as f32;
         Self {
-            filter: BiquadBandPass::new(sample_rate, 50, 6.0),
-            energy: Energy::new(sample_rate as usize / 10),
-            stats: BeatStats::new(1.0, beat_frames_per_s, args.fastest_bpm as f32),
+            filter,
+            energy: Energy::new((sample_rate * band.energy_window_s) as usize),
+            stats: BeatStats::new(1.0, beat_frames_per_s, fastest_onsets_per_min),
         }
     }
+}
 
-    pub fn on_pcm_sample(&mut self, sample_index: u64, x: f32) -> (f32, bool) {
+impl OnsetDetector for BeatDetector {
+    fn on_pcm_sample(&mut self, sample_index: u64, x: f32) -> (f32, bool) {
         let filtered = self.filter.sample(x);
         let energy = self.energy.sample(filtered);
 
@@ -108,4 +159,16 @@ impl BeatDetector {
 
         (energy, is_beat)
     }
+
+    fn energy(&self) -> f32 {
+        self.energy.last()
+    }
+
+    fn cumulative_energy(&self) -> f32 {
+        self.energy.cumulative()
+    }
+
+    fn debug_values(&self) -> [f32; 3] {
+        [self.stats.energy, self.stats.short.avg, self.stats.long.avg]
+    }
 }
@@ -0,0 +1,68 @@
+use serde::Serialize;
+
+use crate::Args;
+
+use super::{
+    agent_tracker::AgentTracker,
+    bpm_tracker::{Bpm, BpmTracker},
+    tempogram::TempoEstimate,
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum BeatTrackerKind {
+    /// A single BPM and phase, switched to a new candidate at bar boundaries
+    Grid,
+    /// Competing tempo and phase hypotheses, each scored against every onset
+    Agents,
+}
+
+/// A tempo and phase of the beat, as considered by a tracker.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct BeatHypothesis {
+    pub bpm: f32,
+    /// Offset of the grid relative to sample 0 in seconds, in `[0, period)`.
+    pub phase: f32,
+    pub score: f32,
+}
+
+/// Turns the onsets into a steady beat grid.
+pub trait BeatTracker {
+    fn on_beat(&mut self, sample_index: u64);
+
+    fn on_tempo_estimate(&mut self, estimate: TempoEstimate);
+
+    /// Force the grid to `bpm` with a beat at `beat_sample`, e.g. from tap tempo. Automatic
+    /// tracking continues from the new grid.
+    fn set_grid(&mut self, bpm: f32, beat_sample: u64);
+
+    /// Meter of the music, see `BarTracker`.
+    fn set_beats_per_bar(&mut self, beats_per_bar: u32);
+
+    fn bpm(&self) -> Bpm;
+
+    /// Offset of the beat grid relative to sample 0 in seconds, in `[0, period)`.
+    fn phase_offset(&self) -> f32;
+
+    fn sample_to_beat_fract(&self, sample_index: u64) -> f32;
+
+    /// How badly the recent onsets fit the grid, the sum of their squared offsets over about 15
+    /// beats.
+    fn phase_error(&self) -> f32;
+
+    fn bpm_confidence(&self) -> f32;
+
+    /// The hypotheses of the tracker, the followed one first.
+    fn hypotheses(&self) -> Vec<BeatHypothesis>;
+
+    fn beat_probability(&self, sample_index: u64) -> f32 {
+        let offset = self.sample_to_beat_fract(sample_index);
+        self.bpm_confidence() * 2.0 * (offset - 0.5).abs()
+    }
+}
+
+pub fn new_beat_tracker(args: &Args, sample_rate: f32) -> Box<dyn BeatTracker> {
+    match args.beat_tracker {
+        BeatTrackerKind::Grid => Box::new(BpmTracker::new(args, sample_rate)),
+        BeatTrackerKind::Agents => Box::new(AgentTracker::new(args, sample_rate)),
+    }
+}
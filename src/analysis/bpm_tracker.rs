@@ -2,52 +2,99 @@ use tracing::debug;
 
 use crate::{ring_buffer::RingBuffer, Args};
 
-pub struct Mode {
-    min_value: u32,
-    values: RingBuffer<u32>,
-    counter: Vec<u32>,
+use super::{
+    beat_tracker::{BeatHypothesis, BeatTracker},
+    tempogram::TempoEstimate,
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum TempoEstimator {
+    /// Most frequent BPM of the recent inter-onset deltas
+    InterOnset,
+    /// Autocorrelation of the onset-strength envelope
+    Autocorrelation,
+    /// Autocorrelation if it is confident enough, inter-onset deltas otherwise
+    Combined,
 }
 
-impl Mode {
-    fn new(min_value: u32, max_value: u32, size: usize) -> Self {
-        Mode {
+/// Kernel density estimate over the recent BPM values, evaluated at `RESOLUTION` steps.
+pub struct TempoDensity {
+    min_value: f32,
+    values: RingBuffer<f32>,
+    kernel: Vec<f32>,
+    density: Vec<f32>,
+}
+
+impl TempoDensity {
+    const RESOLUTION: f32 = 0.1;
+    /// Standard deviation of the gaussian kernel in BPM.
+    const BANDWIDTH: f32 = 0.5;
+
+    fn new(min_value: f32, max_value: f32, size: usize) -> Self {
+        let num_bins = ((max_value - min_value) / Self::RESOLUTION).round() as usize + 1;
+        let radius = (3.0 * Self::BANDWIDTH / Self::RESOLUTION).ceil() as i32;
+        let kernel = (-radius..=radius)
+            .map(|step| {
+                let x = step as f32 * Self::RESOLUTION / Self::BANDWIDTH;
+                (-0.5 * x * x).exp()
+            })
+            .collect();
+
+        TempoDensity {
             min_value,
-            values: RingBuffer::new_with_default(size, min_value),
-            counter: vec![0; (max_value - min_value) as usize],
+            // NaN marks the slots which haven't been filled yet.
+            values: RingBuffer::new_with_default(size, f32::NAN),
+            kernel,
+            density: vec![0.0; num_bins],
+        }
+    }
+
+    fn add_kernel(&mut self, value: f32, weight: f32) {
+        if !value.is_finite() {
+            return;
+        }
+        let center = ((value - self.min_value) / Self::RESOLUTION).round() as isize;
+        let radius = (self.kernel.len() / 2) as isize;
+        for (step, factor) in self.kernel.iter().enumerate() {
+            let index = center + step as isize - radius;
+            if let Some(bin) = usize::try_from(index)
+                .ok()
+                .and_then(|index| self.density.get_mut(index))
+            {
+                *bin += weight * factor;
+            }
         }
     }
 
-    fn sample(&mut self, next: u32) -> u32 {
+    fn sample(&mut self, next: f32) -> f32 {
         let last = self.values.oldest();
         self.values.push(next);
 
-        let last_index = (last - self.min_value) as usize;
-        self.counter[last_index] = self.counter[last_index].saturating_sub(1);
-        let next_index = (next - self.min_value) as usize;
-        self.counter[next_index] += 1;
+        self.add_kernel(last, -1.0);
+        self.add_kernel(next, 1.0);
 
-        let most_frequent = self
-            .counter
+        let densest = self
+            .density
             .iter()
             .enumerate()
-            .max_by_key(|(_index, count)| *count)
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
             .unwrap();
 
-        most_frequent.0 as u32 + self.min_value
+        self.min_value + densest.0 as f32 * Self::RESOLUTION
     }
 }
 
 #[derive(Clone)]
 pub struct Bpm {
-    pub value: u32,
+    pub value: f32,
     pub period: f32,
 }
 
 impl Bpm {
-    fn new(bpm: u32) -> Self {
+    pub fn new(bpm: f32) -> Self {
         Self {
             value: bpm,
-            period: 60.0 / bpm as f32,
+            period: 60.0 / bpm,
         }
     }
 }
@@ -60,15 +107,20 @@ pub struct BpmTracker {
 
     // Universal.
     beat_index: u32,
+    beats_per_bar: u32,
     last_beats: RingBuffer<u64>,
     on_phase_beats: RingBuffer<u64>,
     last_delta: RingBuffer<f32>,
 
     // Period.
     last_delta_sum: f32,
-    bpm_mode: Mode,
+    bpm_density: TempoDensity,
+    tempo_estimator: TempoEstimator,
+    tempo_estimate: Option<TempoEstimate>,
 
     bpm_candidate: Bpm,
+    bpm_candidate_is_periodic: bool,
+    bpm_is_periodic: bool,
     pub bpm: Bpm,
 
     // Phase.
@@ -83,27 +135,39 @@ impl BpmTracker {
     const BEATS_HISTORY_SIZE: usize = 15;
     const DELTA_HISTORY_SIZE: usize = 10;
     const BPM_HISTORY_SIZE: usize = 32;
+    const MIN_TEMPO_CONFIDENCE: f32 = 0.3;
+    /// Maximum distance of an onset to the beat grid in beats, once the grid is locked.
+    const ON_GRID_TOLERANCE: f32 = 0.25;
+    /// Candidates closer than this to the current BPM are adopted without searching a new phase.
+    const BPM_REFINE_RANGE: f32 = 2.0;
 
     pub fn new(args: &Args, sample_rate: f32) -> Self {
-        let rough_bpm = (args.slowest_bpm + args.fastest_bpm) / 2;
+        let slowest_bpm = args.slowest_bpm as f32;
+        let fastest_bpm = args.fastest_bpm as f32;
+        let rough_bpm = 0.5 * (slowest_bpm + fastest_bpm);
         BpmTracker {
             // Constant.
             sample_rate,
             // We use these limits to ignore totally off-beat deltas.
-            slow: Bpm::new(args.slowest_bpm),
-            fast: Bpm::new(args.fastest_bpm),
+            slow: Bpm::new(slowest_bpm),
+            fast: Bpm::new(fastest_bpm),
 
             // Universal.
             beat_index: 0,
+            beats_per_bar: 4,
             last_beats: RingBuffer::new_with_default(Self::BEATS_HISTORY_SIZE, 0),
             on_phase_beats: RingBuffer::new_with_default(Self::BEATS_HISTORY_SIZE, 0),
             last_delta: RingBuffer::new_with_default(Self::DELTA_HISTORY_SIZE, 0.5),
 
             // Period.
             last_delta_sum: 0.5 * Self::DELTA_HISTORY_SIZE as f32,
-            bpm_mode: Mode::new(args.slowest_bpm, args.fastest_bpm, Self::BPM_HISTORY_SIZE),
+            bpm_density: TempoDensity::new(slowest_bpm, fastest_bpm, Self::BPM_HISTORY_SIZE),
+            tempo_estimator: args.tempo_estimator,
+            tempo_estimate: None,
 
             bpm_candidate: Bpm::new(rough_bpm),
+            bpm_candidate_is_periodic: false,
+            bpm_is_periodic: false,
             bpm: Bpm::new(rough_bpm),
 
             // Phase.
@@ -119,21 +183,58 @@ impl BpmTracker {
         self.fast.period < delta_s && delta_s < self.slow.period
     }
 
+    /// Whether the beat is close to the grid. Only checked once the BPM and phase were taken
+    /// from the autocorrelation, which keeps off-beat onsets from dragging the phase.
+    fn fits_grid(&self, sample_index: u64) -> bool {
+        if !self.bpm_is_periodic {
+            return true;
+        }
+        let fract = self.sample_to_beat_fract(sample_index);
+        (fract - fract.round()).abs() < Self::ON_GRID_TOLERANCE
+    }
+
     fn sample_to_phase(&self, sample_index: u64) -> f32 {
         (sample_index - self.phase_origin) as f32 / self.sample_rate - self.phase
     }
 
-    pub fn sample_to_beat_fract(&self, sample_index: u64) -> f32 {
-        (self.sample_to_phase(sample_index) / self.bpm.period).fract()
+    fn estimate_bpm(&mut self) {
+        let bpm = 60.0 * (Self::DELTA_HISTORY_SIZE as f32) / self.last_delta_sum;
+        let inter_onset_bpm = self.bpm_density.sample(bpm);
+
+        let periodic_bpm = self
+            .tempo_estimate
+            .as_ref()
+            .filter(|estimate| match self.tempo_estimator {
+                TempoEstimator::InterOnset => false,
+                TempoEstimator::Autocorrelation => true,
+                TempoEstimator::Combined => estimate.confidence >= Self::MIN_TEMPO_CONFIDENCE,
+            })
+            .map(|estimate| estimate.bpm.clamp(self.slow.value, self.fast.value))
+            // The autocorrelation picks the tempo, the onset deltas are more precise though.
+            .map(|bpm| {
+                if (bpm - inter_onset_bpm).abs() < Self::BPM_REFINE_RANGE {
+                    inter_onset_bpm
+                } else {
+                    bpm
+                }
+            });
+
+        self.bpm_candidate_is_periodic = periodic_bpm.is_some();
+        self.bpm_candidate = Bpm::new(periodic_bpm.unwrap_or(inter_onset_bpm));
     }
 
-    fn estimate_bpm(&mut self) {
-        let bpm = (60.0 * (Self::DELTA_HISTORY_SIZE as f32) / self.last_delta_sum).round() as u32;
-        self.bpm_candidate = Bpm::new(self.bpm_mode.sample(bpm));
+    /// Adopt the slightly different candidate period while keeping the beat fract at
+    /// `sample_index` where it is.
+    fn refine_period(&mut self, sample_index: u64) {
+        let time = (sample_index - self.phase_origin) as f32 / self.sample_rate;
+        let beats = (time - self.phase) / self.bpm.period;
+        self.bpm = self.bpm_candidate.clone();
+        self.phase = time - beats * self.bpm.period;
     }
 
-    fn check_bpm_candidate(&mut self) {
-        if self.bpm_candidate.value == self.bpm.value {
+    fn check_bpm_candidate(&mut self, sample_index: u64) {
+        if (self.bpm_candidate.value - self.bpm.value).abs() < Self::BPM_REFINE_RANGE {
+            self.refine_period(sample_index);
             return;
         }
 
@@ -150,12 +251,33 @@ impl BpmTracker {
             .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
             .unwrap();
 
-        if best_candidate_phase.1 < error_now {
+        // Onsets between the beats fit faster tempi better, so the phase error can't decide
+        // between octaves. The autocorrelation already took care of that, including the phase.
+        let periodic_phase = self
+            .tempo_estimate
+            .as_ref()
+            .filter(|_| self.bpm_candidate_is_periodic)
+            .map(|estimate| {
+                let beat_s = (estimate.beat_sample as i64 - self.phase_origin as i64) as f32
+                    / self.sample_rate;
+                beat_s.rem_euclid(self.bpm_candidate.period)
+            });
+
+        if let Some(phase) = periodic_phase {
+            self.bpm = self.bpm_candidate.clone();
+            self.bpm_is_periodic = true;
+            self.phase = phase;
+            debug!(
+                "Switched BPM to {:.1} following the autocorrelation",
+                self.bpm.value
+            );
+        } else if best_candidate_phase.1 < error_now {
             self.bpm = self.bpm_candidate.clone();
+            self.bpm_is_periodic = false;
             self.phase = best_candidate_phase.0;
-            debug!("Switched BPM to {}", self.bpm.value);
+            debug!("Switched BPM to {:.1}", self.bpm.value);
         } else {
-            debug!("Denied BPM switch to {}", self.bpm_candidate.value);
+            debug!("Denied BPM switch to {:.1}", self.bpm_candidate.value);
         }
     }
 
@@ -170,15 +292,20 @@ impl BpmTracker {
             })
             .sum::<f32>()
     }
+}
 
-    pub fn on_beat(&mut self, sample_index: u64) {
+impl BeatTracker for BpmTracker {
+    fn on_beat(&mut self, sample_index: u64) {
         self.beat_index += 1;
 
         /* MOVE THE ORIGIN CLOSER TO THE SERIES TO PREVENT FLOAT IMPRECISION */
         let oldest_beat = self.on_phase_beats.oldest();
         let delta_s = (oldest_beat - self.phase_origin) as f32 / self.sample_rate;
         let num_periods = (delta_s / self.bpm.period).floor();
-        self.phase_origin += (num_periods * self.bpm.period) as u64;
+        let shift = (num_periods * self.bpm.period * self.sample_rate) as u64;
+        self.phase_origin += shift;
+        // Compensate the rounding to whole samples, the grid must not move.
+        self.phase += num_periods * self.bpm.period - shift as f32 / self.sample_rate;
 
         // debug!(
         //     "{oldest_beat} {} {}",
@@ -193,16 +320,18 @@ impl BpmTracker {
         self.last_beats.push(sample_index);
         // Ignore the delta if it doesn't fit our expectations.
         if self.delta_fits_bpm_range(delta_s) {
-            self.on_phase_beats.push(sample_index);
+            if self.fits_grid(sample_index) {
+                self.on_phase_beats.push(sample_index);
+            }
 
             self.last_delta_sum += delta_s - self.last_delta.oldest();
             self.last_delta.push(delta_s);
         }
         self.estimate_bpm();
 
-        // Check the BPM candidate every fourth beat.
-        if self.beat_index & 0b11 == 0 {
-            self.check_bpm_candidate();
+        // Check the BPM candidate once per bar.
+        if self.beat_index % self.beats_per_bar == 0 {
+            self.check_bpm_candidate(sample_index);
         }
 
         /* FIT THE PHASE ONTO THE LAST KNOWN BEATS USING GRADIENT DESCENT */
@@ -219,12 +348,88 @@ impl BpmTracker {
         self.phase_error_dt = error_dt;
     }
 
-    pub fn bpm_confidence(&self) -> f32 {
+    fn on_tempo_estimate(&mut self, estimate: TempoEstimate) {
+        self.tempo_estimate = Some(estimate);
+    }
+
+    /// Fills the history with onsets on the new grid, as if it had been tracked all along. The
+    /// onsets that follow refine it like any other grid.
+    fn set_grid(&mut self, bpm: f32, beat_sample: u64) {
+        self.bpm = Bpm::new(bpm.clamp(self.slow.value, self.fast.value));
+        self.bpm_candidate = self.bpm.clone();
+        self.bpm_candidate_is_periodic = false;
+        self.bpm_is_periodic = false;
+        // The autocorrelation is followed again once it has seen the music since.
+        self.tempo_estimate = None;
+
+        let period_samples = self.bpm.period * self.sample_rate;
+        for beat in (0..Self::BEATS_HISTORY_SIZE).rev() {
+            let offset = (beat as f32 * period_samples) as u64;
+            self.on_phase_beats.push(beat_sample.saturating_sub(offset));
+        }
+        for _ in 0..Self::DELTA_HISTORY_SIZE {
+            self.last_delta.push(self.bpm.period);
+        }
+        self.last_delta_sum = Self::DELTA_HISTORY_SIZE as f32 * self.bpm.period;
+        self.bpm_density =
+            TempoDensity::new(self.slow.value, self.fast.value, Self::BPM_HISTORY_SIZE);
+
+        self.phase_origin = self.on_phase_beats.oldest();
+        let beat_s = (beat_sample - self.phase_origin) as f32 / self.sample_rate;
+        self.phase = beat_s.rem_euclid(self.bpm.period);
+        self.phase_error = 0.0;
+        self.phase_error_dt = 0.0;
+    }
+
+    fn set_beats_per_bar(&mut self, beats_per_bar: u32) {
+        self.beats_per_bar = beats_per_bar.max(1);
+    }
+
+    fn bpm(&self) -> Bpm {
+        self.bpm.clone()
+    }
+
+    fn phase_offset(&self) -> f32 {
+        let origin_s = self.phase_origin as f64 / self.sample_rate as f64;
+        (origin_s + self.phase as f64).rem_euclid(self.bpm.period as f64) as f32
+    }
+
+    fn sample_to_beat_fract(&self, sample_index: u64) -> f32 {
+        (self.sample_to_phase(sample_index) / self.bpm.period).fract()
+    }
+
+    fn phase_error(&self) -> f32 {
+        self.phase_error
+    }
+
+    fn bpm_confidence(&self) -> f32 {
         (0.5 / self.phase_error).min(1.0)
     }
 
-    pub fn beat_probability(&self, sample_index: u64) -> f32 {
-        let offset = self.sample_to_beat_fract(sample_index);
-        self.bpm_confidence() * 2.0 * (offset - 0.5).abs()
+    /// Only the current grid, the phase of a candidate is searched once per bar.
+    fn hypotheses(&self) -> Vec<BeatHypothesis> {
+        vec![BeatHypothesis {
+            bpm: self.bpm.value,
+            phase: self.phase_offset(),
+            score: self.bpm_confidence(),
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tempo_density_ignores_non_finite_values() {
+        let mut density = TempoDensity::new(60.0, 200.0, 4);
+        density.sample(128.0);
+        for value in [f32::INFINITY, f32::NEG_INFINITY, f32::NAN] {
+            let densest = density.sample(value);
+            assert!(
+                (densest - 128.0).abs() < TempoDensity::RESOLUTION,
+                "densest {densest}"
+            );
+        }
     }
 }
@@ -0,0 +1,105 @@
+use std::{f32::consts::PI, ffi::c_void, mem};
+
+/// Energy of the spectrum per pitch class, C first, tuned to the music.
+///
+/// The peaks of the spectrum are located between the bins by parabolic interpolation and
+/// assigned to the closest semitone. Recordings aren't always tuned to 440 Hz, so the deviation
+/// of the peaks from the semitones is averaged over time and compensated before folding.
+pub struct Chroma {
+    bin_fq: f32,
+    min_bin: usize,
+    max_bin: usize,
+    /// Sum of the deviations of the peaks from the semitones as unit phasors, weighted by the
+    /// magnitude of the peaks and forgotten per frame.
+    tuning_phasor: [f32; 2],
+
+    /// Deviation of the music from 440 Hz tuning, in semitones.
+    pub tuning: f32,
+    /// Normalized to a maximum of 1, smoothed over a few frames.
+    pub chroma: [f32; 12],
+}
+
+impl Chroma {
+    pub const NUM_PITCH_CLASSES: usize = 12;
+    const MIN_FQ: f32 = 80.0;
+    const MAX_FQ: f32 = 5000.0;
+    const TUNING_DECAY: f32 = 0.999;
+    const SMOOTHING: f32 = 0.8;
+    /// Frames quieter than this leave the chroma as it is.
+    const MIN_MAGNITUDE: f32 = 1e-3;
+
+    pub fn new(dft_size: usize, sample_rate: f32) -> Self {
+        let bin_fq = sample_rate / dft_size as f32;
+        Self {
+            bin_fq,
+            min_bin: ((Self::MIN_FQ / bin_fq).floor() as usize).max(1),
+            max_bin: ((Self::MAX_FQ / bin_fq).ceil() as usize).min(dft_size / 2 - 1),
+            tuning_phasor: [0.0; 2],
+
+            tuning: 0.0,
+            chroma: [0.0; Self::NUM_PITCH_CLASSES],
+        }
+    }
+
+    /// MIDI pitch of the peak at `bin`, refined with the neighbouring magnitudes.
+    fn peak_pitch(&self, magnitudes: &[f32], bin: usize) -> f32 {
+        let [left, center, right] =
+            [bin - 1, bin, bin + 1].map(|index| magnitudes[index].max(f32::EPSILON).ln());
+        let curvature = left - 2.0 * center + right;
+        let offset = if curvature < 0.0 {
+            (0.5 * (left - right) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        let fq = (bin as f32 + offset) * self.bin_fq;
+        69.0 + 12.0 * (fq / 440.0).log2()
+    }
+
+    /// Fold the next frame of magnitudes, one per frequency bin.
+    pub fn on_spectrum(&mut self, magnitudes: &[f32]) {
+        let peaks = (self.min_bin..=self.max_bin)
+            .filter(|&bin| {
+                magnitudes[bin] > magnitudes[bin - 1] && magnitudes[bin] >= magnitudes[bin + 1]
+            })
+            .map(|bin| (self.peak_pitch(magnitudes, bin), magnitudes[bin]))
+            .collect::<Vec<_>>();
+
+        self.tuning_phasor = self.tuning_phasor.map(|x| Self::TUNING_DECAY * x);
+        for &(pitch, magnitude) in &peaks {
+            let deviation = 2.0 * PI * (pitch - pitch.round());
+            self.tuning_phasor[0] += magnitude * deviation.cos();
+            self.tuning_phasor[1] += magnitude * deviation.sin();
+        }
+        self.tuning = self.tuning_phasor[1].atan2(self.tuning_phasor[0]) / (2.0 * PI);
+
+        let mut frame = [0.0; Self::NUM_PITCH_CLASSES];
+        for &(pitch, magnitude) in &peaks {
+            // MIDI pitch 0 is a C.
+            let pitch_class = ((pitch - self.tuning).round() as i32).rem_euclid(12);
+            frame[pitch_class as usize] += magnitude;
+        }
+
+        let max = frame.iter().copied().fold(0.0, f32::max);
+        if max < Self::MIN_MAGNITUDE {
+            return;
+        }
+        for (value, next) in self.chroma.iter_mut().zip(frame) {
+            *value = Self::SMOOTHING * *value + (1.0 - Self::SMOOTHING) * next / max;
+        }
+    }
+
+    pub fn serialized_size() -> usize {
+        mem::size_of::<i32>() + Self::NUM_PITCH_CLASSES * mem::size_of::<f32>()
+    }
+
+    /// Same layout as `Dft::write_log_bins_to_pointer`.
+    pub fn write_to_pointer(&self, target: *mut c_void) {
+        unsafe {
+            *target.cast::<u32>() = Self::NUM_PITCH_CLASSES as u32;
+            let target = target.add(mem::size_of::<i32>());
+            self.chroma
+                .as_ptr()
+                .copy_to(target.cast(), Self::NUM_PITCH_CLASSES);
+        }
+    }
+}
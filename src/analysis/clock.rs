@@ -0,0 +1,126 @@
+use std::{
+    cell::Cell,
+    rc::Rc,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// Source of the time of the analysis.
+pub trait Clock {
+    /// Time since the start of the clock.
+    fn elapsed(&self) -> Duration;
+}
+
+pub struct WallClock {
+    epoch: Instant,
+}
+
+impl WallClock {
+    pub fn new() -> Self {
+        WallClock {
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for WallClock {
+    fn elapsed(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+}
+
+/// A clock that only moves when advanced explicitly. Clones share the same time.
+#[derive(Clone, Default)]
+pub struct ManualClock(Rc<Cell<Duration>>);
+
+impl ManualClock {
+    pub fn advance(&self, delta: Duration) {
+        self.0.set(self.0.get() + delta);
+    }
+}
+
+impl Clock for ManualClock {
+    fn elapsed(&self) -> Duration {
+        self.0.get()
+    }
+}
+
+/// How many samples the analysis consumes per tick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pacing {
+    /// As many as correspond to the clock time elapsed since the last tick.
+    Elapsed,
+    /// Everything that is available in the input buffer.
+    Available,
+    /// A fixed number of samples, or fewer if not available.
+    Fixed(usize),
+}
+
+impl FromStr for Pacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "elapsed" => Ok(Pacing::Elapsed),
+            "available" => Ok(Pacing::Available),
+            step => step.parse().map(Pacing::Fixed).map_err(|_| {
+                format!("Expected 'elapsed', 'available' or a number of samples, got '{step}'")
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::{
+        analysis::Analysis,
+        audio::{
+            generator::{Generator, Signal},
+            stereo::Stereo,
+        },
+        Args,
+    };
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+    const HOP: usize = 735;
+
+    /// Tempo, beat position and beat count after every tick over a generated kick pattern.
+    fn run(args: &Args) -> Vec<(f32, f32, u32)> {
+        let file = Generator::new(Signal::Kicks, &args.generator, SAMPLE_RATE).render(10.0);
+        let clock = ManualClock::default();
+        let sample_rate = SAMPLE_RATE as f32;
+        let mut analysis = Analysis::with_clock(args, sample_rate, None, Box::new(clock.clone()));
+        let mut stereo = Stereo::new(analysis.buf_size);
+
+        file.samples
+            .chunks(2 * HOP)
+            .map(|chunk| {
+                stereo.write_samples(chunk);
+                clock.advance(Duration::from_secs_f32(HOP as f32 / sample_rate));
+                analysis.on_tick(&stereo);
+                let bpm = analysis.bpm_tracker.bpm().value;
+                (bpm, analysis.beat_fract, analysis.real_beats)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn manual_clock_with_fixed_pacing_is_repeatable() {
+        let args = Args::parse_from(["visualize-rs", "--pacing", &HOP.to_string()]);
+        let first = run(&args);
+        let second = run(&args);
+
+        assert!(first.last().is_some_and(|&(_, _, beats)| beats > 0));
+        assert!(first == second);
+    }
+}
@@ -0,0 +1,109 @@
+/// Shape of the spectrum of every tick, and the zero-crossing rate of the samples in between.
+///
+/// Silent ticks leave all of them as they are, so shaders don't flash on every gap.
+pub struct SpectralDescriptors {
+    bin_fq: f32,
+    magnitudes: Vec<f32>,
+    previous_magnitudes: Vec<f32>,
+    last_sample: f32,
+    crossings: u32,
+    samples: u32,
+
+    /// Center of mass of the magnitudes in Hz, how bright the sound is.
+    pub centroid: f32,
+    /// Standard deviation of the magnitudes around the centroid in Hz.
+    pub spread: f32,
+    /// Frequency in Hz below which `ROLLOFF` of the energy is.
+    pub rolloff: f32,
+    /// Geometric over arithmetic mean of the energies, from 0 (a tone) to 1 (white noise).
+    pub flatness: f32,
+    /// Rectified increase of the magnitudes over the previous tick relative to their sum.
+    pub flux: f32,
+    /// Sign changes per sample.
+    pub zero_crossing_rate: f32,
+}
+
+impl SpectralDescriptors {
+    const ROLLOFF: f32 = 0.85;
+    /// Ticks quieter than this leave the descriptors as they are.
+    const MIN_MAGNITUDE: f32 = 1e-3;
+
+    pub fn new(dft_size: usize, sample_rate: f32) -> Self {
+        let num_fqs = dft_size / 2 + 1;
+        Self {
+            bin_fq: sample_rate / dft_size as f32,
+            magnitudes: Vec::with_capacity(num_fqs),
+            previous_magnitudes: vec![0.0; num_fqs],
+            last_sample: 0.0,
+            crossings: 0,
+            samples: 0,
+
+            centroid: 0.0,
+            spread: 0.0,
+            rolloff: 0.0,
+            flatness: 0.0,
+            flux: 0.0,
+            zero_crossing_rate: 0.0,
+        }
+    }
+
+    pub fn on_pcm_sample(&mut self, x: f32) {
+        if (x >= 0.0) != (self.last_sample >= 0.0) {
+            self.crossings += 1;
+        }
+        self.samples += 1;
+        self.last_sample = x;
+    }
+
+    /// Update the descriptors from the magnitudes of the latest DFT.
+    pub fn on_spectrum(&mut self, magnitudes: impl Iterator<Item = f32>) {
+        if self.samples > 0 {
+            self.zero_crossing_rate = self.crossings as f32 / self.samples as f32;
+            self.crossings = 0;
+            self.samples = 0;
+        }
+
+        self.magnitudes.clear();
+        self.magnitudes.extend(magnitudes);
+        // Skip the DC bin.
+        let bins = || self.magnitudes.iter().enumerate().skip(1);
+
+        let total = bins().map(|(_, magnitude)| magnitude).sum::<f32>();
+        if total < Self::MIN_MAGNITUDE {
+            return;
+        }
+        let fq = |index: usize| index as f32 * self.bin_fq;
+
+        self.centroid = bins().map(|(index, m)| fq(index) * m).sum::<f32>() / total;
+        let variance = bins()
+            .map(|(index, m)| (fq(index) - self.centroid).powi(2) * m)
+            .sum::<f32>();
+        self.spread = (variance / total).sqrt();
+
+        let energy = bins().map(|(_, m)| m * m).sum::<f32>();
+        let mut cumulative = 0.0;
+        for (index, magnitude) in bins() {
+            cumulative += magnitude * magnitude;
+            if cumulative >= Self::ROLLOFF * energy {
+                self.rolloff = fq(index);
+                break;
+            }
+        }
+
+        let num_bins = (self.magnitudes.len() - 1) as f32;
+        let mean_log_energy = bins()
+            .map(|(_, m)| (m * m).max(f32::EPSILON).ln())
+            .sum::<f32>()
+            / num_bins;
+        self.flatness = (mean_log_energy.exp() / (energy / num_bins)).clamp(0.0, 1.0);
+
+        let increase = self
+            .magnitudes
+            .iter()
+            .zip(&self.previous_magnitudes)
+            .map(|(magnitude, previous)| (magnitude - previous).max(0.0))
+            .sum::<f32>();
+        self.flux = increase / total;
+        self.previous_magnitudes.copy_from_slice(&self.magnitudes);
+    }
+}
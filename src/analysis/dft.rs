@@ -5,11 +5,12 @@ use rustfft::num_complex::Complex;
 
 pub struct Dft {
     r2c: Arc<dyn realfft::RealToComplex<f32>>,
-    // c2r: Arc<dyn realfft::ComplexToReal<f32>>,
+    c2r: Arc<dyn realfft::ComplexToReal<f32>>,
     blackman_harris: Vec<f32>,
 
     pub input: Vec<f32>,
     scratch: Vec<Complex<f32>>,
+    inverse_scratch: Vec<Complex<f32>>,
     output: Vec<Complex<f32>>,
 
     fq_decay: Vec<f32>,
@@ -27,10 +28,11 @@ impl Dft {
     pub fn new(length: usize, sample_rate: f32) -> Self {
         let mut real_planner = realfft::RealFftPlanner::<f32>::new();
         let r2c = real_planner.plan_fft_forward(length);
-        // let c2r = real_planner.plan_fft_inverse(length);
+        let c2r = real_planner.plan_fft_inverse(length);
 
         let input = r2c.make_input_vec();
         let scratch = r2c.make_scratch_vec();
+        let inverse_scratch = c2r.make_scratch_vec();
         let output = r2c.make_output_vec();
 
         assert_eq!(input.len(), length);
@@ -77,10 +79,11 @@ impl Dft {
 
         Dft {
             r2c,
-            // c2r,
+            c2r,
             blackman_harris,
             input,
             scratch,
+            inverse_scratch,
             output,
             fq_decay,
             fq_db,
@@ -126,18 +129,53 @@ impl Dft {
     }
 
     pub fn write_log_bins_to_pointer(&self, target: *mut c_void) {
+        self.write_bins_to_pointer(self.log_bins(), target);
+    }
+
+    /// Same layout as `write_log_bins_to_pointer`, for another spectrum of the same size.
+    pub fn write_spectrum_log_bins_to_pointer(&self, spectrum: &[f32], target: *mut c_void) {
+        self.write_bins_to_pointer(self.spectrum_log_bins(spectrum), target);
+    }
+
+    fn write_bins_to_pointer(&self, bins: impl Iterator<Item = f32>, target: *mut c_void) {
         unsafe {
             *target.cast::<u32>() = u32::try_from(self.num_bins).unwrap();
             let target = target.add(mem::size_of::<i32>());
             let target = target.cast::<f32>();
 
-            for (index, (start, end)) in self.bin_indices.iter().enumerate() {
-                let slice = &self.fq_db[*start..*end + 1];
-                *target.add(index) = slice.iter().sum::<f32>() / slice.len() as f32;
+            for (index, value) in bins.take(self.num_bins).enumerate() {
+                *target.add(index) = value;
             }
         }
     }
 
+    /// The decayed spectrum in dB, averaged over logarithmically spaced bins.
+    pub fn log_bins(&self) -> impl Iterator<Item = f32> + '_ {
+        self.bin_indices.iter().map(|(start, end)| {
+            let slice = &self.fq_db[*start..*end + 1];
+            slice.iter().sum::<f32>() / slice.len() as f32
+        })
+    }
+
+    /// Magnitudes of another spectrum of the same size in dB, averaged over the same bins as
+    /// `log_bins`.
+    pub fn spectrum_log_bins<'a>(&'a self, spectrum: &'a [f32]) -> impl Iterator<Item = f32> + 'a {
+        self.bin_indices.iter().map(|(start, end)| {
+            let slice = &spectrum[*start..*end + 1];
+            let db = slice.iter().map(|x| 20.0 * x.max(1e-6).log10());
+            db.sum::<f32>() / slice.len() as f32
+        })
+    }
+
+    pub fn num_log_bins(&self) -> usize {
+        self.num_bins
+    }
+
+    /// Magnitudes of the last transform, one per frequency bin.
+    pub fn magnitudes(&self) -> impl Iterator<Item = f32> + '_ {
+        self.output.iter().map(|x| x.norm())
+    }
+
     pub fn run_transform(&mut self) {
         // Hamming window for smoother DFT results.
         for (val, factor) in self.input.iter_mut().zip(self.blackman_harris.iter()) {
@@ -162,17 +200,21 @@ impl Dft {
         // }
     }
 
-    // fn run_inverse(&mut self) {
-    //     self.c2r
-    //         .process_with_scratch(&mut self.output, &mut self.input, &mut self.scratch)
-    //         .unwrap();
-    // }
+    fn run_inverse(&mut self) {
+        self.c2r
+            .process_with_scratch(&mut self.output, &mut self.input, &mut self.inverse_scratch)
+            .unwrap();
+    }
 
-    // pub fn autocorrelate(&mut self) {
-    //     self.run_transform();
-    //     for x in self.output.iter_mut() {
-    //         *x = *x * x.conj();
-    //     }
-    //     self.run_inverse();
-    // }
+    /// Replace the input with its (circular, unnormalized) autocorrelation. No window is applied,
+    /// zero-pad the second half of the input to get the linear autocorrelation.
+    pub fn autocorrelate(&mut self) {
+        self.r2c
+            .process_with_scratch(&mut self.input, &mut self.output, &mut self.scratch)
+            .unwrap();
+        for x in self.output.iter_mut() {
+            *x = Complex::new(x.norm_sqr(), 0.0);
+        }
+        self.run_inverse();
+    }
 }
@@ -0,0 +1,467 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tracing::{info, instrument};
+
+use crate::{
+    audio::file::AudioFile,
+    error::{Error, VResult},
+    Args,
+};
+
+use super::offline;
+
+/// Evaluate the beat tracking against annotated beats. The annotations for `track.wav` are
+/// expected in `track.beats`, one timestamp in seconds per line.
+#[derive(clap::Args, Debug, Clone)]
+pub struct EvaluateArgs {
+    /// The audio files to evaluate (WAV or FLAC)
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Tolerance window of the F-measure in seconds
+    #[arg(long, default_value = "0.07")]
+    pub tolerance_s: f64,
+
+    /// Ignore beats in the first seconds, giving the trackers time to settle
+    #[arg(long, default_value = "5.0")]
+    pub skip_s: f64,
+
+    /// Write the results as JSON to this path
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// Read a `.beats` file. Lines may contain further columns (e.g. the beat position in the bar),
+/// only the first one is used. Empty lines and lines starting with `#` are ignored.
+fn load_annotations(path: &Path) -> VResult<Vec<f64>> {
+    let content = fs::read_to_string(path)?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let column = line.split_whitespace().next().unwrap_or_default();
+            column.parse::<f64>().map_err(|err| {
+                let msg = format!("Invalid beat '{line}' in '{}': {err}", path.display());
+                Error::Local(msg)
+            })
+        })
+        .collect()
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    Some(values[values.len() / 2])
+}
+
+fn intervals(beats: &[f64]) -> Vec<f64> {
+    beats.windows(2).map(|pair| pair[1] - pair[0]).collect()
+}
+
+/// Tempo of a beat sequence from its median inter-beat interval.
+fn tempo(beats: &[f64]) -> Option<f64> {
+    median(&mut intervals(beats)).map(|interval| 60.0 / interval)
+}
+
+/// Greedy one-to-one matching of beats within the tolerance window.
+fn f_measure(reference: &[f64], estimated: &[f64], tolerance_s: f64) -> (f64, f64, f64) {
+    if reference.is_empty() || estimated.is_empty() {
+        return (0.0, 0.0, 0.0);
+    }
+
+    let mut used = vec![false; estimated.len()];
+    let mut matches = 0;
+    for beat in reference {
+        let closest = estimated
+            .iter()
+            .enumerate()
+            .filter(|(index, estimate)| !used[*index] && (*estimate - beat).abs() <= tolerance_s)
+            .min_by(|a, b| (a.1 - beat).abs().partial_cmp(&(b.1 - beat).abs()).unwrap());
+        if let Some((index, _)) = closest {
+            used[index] = true;
+            matches += 1;
+        }
+    }
+
+    let precision = matches as f64 / estimated.len() as f64;
+    let recall = matches as f64 / reference.len() as f64;
+    let f = if matches == 0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    };
+    (f, precision, recall)
+}
+
+/// Continuity-based accuracy: returns the longest correct segment and the total of correct beats,
+/// both relative to the number of reference or estimated beats, whichever is larger (as in
+/// mir_eval). An estimated beat is correct when it lies within `THRESHOLD` of the local reference
+/// interval of a reference beat and, unless it is the first one, the interval to the previous
+/// estimate matches the reference interval within the same threshold.
+fn continuity(reference: &[f64], estimated: &[f64]) -> (f64, f64) {
+    const THRESHOLD: f64 = 0.175;
+
+    if reference.len() < 2 || estimated.is_empty() {
+        return (0.0, 0.0);
+    }
+
+    let previous = std::iter::once(None).chain(estimated.iter().copied().map(Some));
+    let correct = estimated.iter().zip(previous).map(|(&beat, previous)| {
+        let nearest = reference
+            .iter()
+            .enumerate()
+            .min_by(|a, b| (a.1 - beat).abs().partial_cmp(&(b.1 - beat).abs()).unwrap())
+            .map(|(index, _)| index)
+            .unwrap();
+        let local_interval = if nearest + 1 < reference.len() {
+            reference[nearest + 1] - reference[nearest]
+        } else {
+            reference[nearest] - reference[nearest - 1]
+        };
+
+        let phase_ok = (beat - reference[nearest]).abs() < THRESHOLD * local_interval;
+        let period_ok = previous.map_or(true, |previous| {
+            ((beat - previous) - local_interval).abs() < THRESHOLD * local_interval
+        });
+        phase_ok && period_ok
+    });
+
+    let (longest, total, _) = correct.fold((0, 0, 0), |(longest, total, run), correct| {
+        let run = if correct { run + 1 } else { 0 };
+        (longest.max(run), total + usize::from(correct), run)
+    });
+
+    let num_beats = reference.len().max(estimated.len()) as f64;
+    (longest as f64 / num_beats, total as f64 / num_beats)
+}
+
+/// The metrical variations of the reference which are accepted by the "allowed metrical levels"
+/// variant of the continuity metrics: off-beat, double tempo and both phases of half tempo.
+fn metrical_variations(reference: &[f64]) -> Vec<Vec<f64>> {
+    let off_beat = reference
+        .windows(2)
+        .map(|pair| 0.5 * (pair[0] + pair[1]))
+        .collect::<Vec<_>>();
+    let double = reference
+        .iter()
+        .zip(off_beat.iter().map(Some).chain(std::iter::once(None)))
+        .flat_map(|(beat, off_beat)| std::iter::once(*beat).chain(off_beat.copied()))
+        .collect::<Vec<_>>();
+    let half_odd = reference.iter().step_by(2).copied().collect();
+    let half_even = reference.iter().skip(1).step_by(2).copied().collect();
+    vec![off_beat, double, half_odd, half_even]
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum TempoClass {
+    Correct,
+    Double,
+    Half,
+    Triple,
+    Third,
+    Other,
+    Unknown,
+}
+
+impl TempoClass {
+    /// Relative tolerance for tempo estimates.
+    const TOLERANCE: f64 = 0.04;
+
+    pub fn classify(reference_bpm: Option<f64>, estimated_bpm: Option<f64>) -> Self {
+        let (Some(reference_bpm), Some(estimated_bpm)) = (reference_bpm, estimated_bpm) else {
+            return Self::Unknown;
+        };
+
+        [
+            (1.0, Self::Correct),
+            (2.0, Self::Double),
+            (0.5, Self::Half),
+            (3.0, Self::Triple),
+            (1.0 / 3.0, Self::Third),
+        ]
+        .into_iter()
+        .find(|(factor, _)| {
+            let expected = factor * reference_bpm;
+            ((estimated_bpm - expected) / expected).abs() <= Self::TOLERANCE
+        })
+        .map_or(Self::Other, |(_, class)| class)
+    }
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct BeatScores {
+    pub f_measure: f64,
+    pub precision: f64,
+    pub recall: f64,
+    pub cml_c: f64,
+    pub cml_t: f64,
+    pub aml_c: f64,
+    pub aml_t: f64,
+}
+
+impl BeatScores {
+    fn new(reference: &[f64], estimated: &[f64], tolerance_s: f64) -> Self {
+        let (f_measure, precision, recall) = f_measure(reference, estimated, tolerance_s);
+        let (cml_c, cml_t) = continuity(reference, estimated);
+        let (aml_c, aml_t) = metrical_variations(reference)
+            .iter()
+            .map(|variation| continuity(variation, estimated))
+            .fold((cml_c, cml_t), |(c, t), (vc, vt)| (c.max(vc), t.max(vt)));
+
+        BeatScores {
+            f_measure,
+            precision,
+            recall,
+            cml_c,
+            cml_t,
+            aml_c,
+            aml_t,
+        }
+    }
+
+    fn mean(scores: &[&BeatScores]) -> Self {
+        let n = scores.len().max(1) as f64;
+        let sum = |get: fn(&BeatScores) -> f64| scores.iter().map(|s| get(s)).sum::<f64>() / n;
+        BeatScores {
+            f_measure: sum(|s| s.f_measure),
+            precision: sum(|s| s.precision),
+            recall: sum(|s| s.recall),
+            cml_c: sum(|s| s.cml_c),
+            cml_t: sum(|s| s.cml_t),
+            aml_c: sum(|s| s.aml_c),
+            aml_t: sum(|s| s.aml_t),
+        }
+    }
+
+    fn print(&self, name: &str, sequence: &str) {
+        println!(
+            "{name:<32} {sequence:<8} {:>6.3} {:>6.3} {:>6.3} {:>6.3} {:>6.3} {:>6.3} {:>6.3}",
+            self.f_measure,
+            self.precision,
+            self.recall,
+            self.cml_c,
+            self.cml_t,
+            self.aml_c,
+            self.aml_t
+        );
+    }
+}
+
+#[derive(Serialize)]
+pub struct TrackResult {
+    pub input: PathBuf,
+    pub reference_bpm: Option<f64>,
+    pub estimated_bpm: Option<f64>,
+    pub tempo_class: TempoClass,
+    /// Scores of the raw beat detector output.
+    pub detector: BeatScores,
+    /// Scores of the beat grid of the BPM tracker.
+    pub tracker: BeatScores,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    pub tracks: Vec<TrackResult>,
+    pub detector: BeatScores,
+    pub tracker: BeatScores,
+    /// Fraction of tracks with the correct tempo.
+    pub tempo_accuracy_1: f64,
+    /// Fraction of tracks with the correct tempo, allowing octave (and triple) errors.
+    pub tempo_accuracy_2: f64,
+}
+
+fn evaluate_track(args: &Args, evaluate_args: &EvaluateArgs, input: &Path) -> VResult<TrackResult> {
+    let annotations = input.with_extension("beats");
+    let skip = |beats: Vec<f64>| -> Vec<f64> {
+        beats
+            .into_iter()
+            .filter(|time| *time >= evaluate_args.skip_s)
+            .collect()
+    };
+
+    let reference = skip(load_annotations(&annotations)?);
+    let file = AudioFile::decode(input)?;
+    let report = offline::run(args, input.display().to_string(), &file);
+
+    let detected = skip(report.beats.iter().map(|record| record.time).collect());
+    let grid = skip(report.grid_beats.iter().map(|record| record.time).collect());
+
+    let reference_bpm = tempo(&reference);
+    let estimated_bpm = tempo(&grid);
+
+    Ok(TrackResult {
+        input: input.to_owned(),
+        reference_bpm,
+        estimated_bpm,
+        tempo_class: TempoClass::classify(reference_bpm, estimated_bpm),
+        detector: BeatScores::new(&reference, &detected, evaluate_args.tolerance_s),
+        tracker: BeatScores::new(&reference, &grid, evaluate_args.tolerance_s),
+    })
+}
+
+#[instrument(skip(args))]
+pub fn evaluate(args: &Args, evaluate_args: &EvaluateArgs) -> VResult<()> {
+    let tracks = evaluate_args
+        .inputs
+        .iter()
+        .map(|input| evaluate_track(args, evaluate_args, input))
+        .collect::<VResult<Vec<_>>>()?;
+
+    let detector = BeatScores::mean(&tracks.iter().map(|t| &t.detector).collect::<Vec<_>>());
+    let tracker = BeatScores::mean(&tracks.iter().map(|t| &t.tracker).collect::<Vec<_>>());
+    let num_tracks = tracks.len() as f64;
+    let count = |accept: fn(TempoClass) -> bool| {
+        tracks.iter().filter(|t| accept(t.tempo_class)).count() as f64 / num_tracks
+    };
+    let summary = Summary {
+        detector,
+        tracker,
+        tempo_accuracy_1: count(|class| class == TempoClass::Correct),
+        tempo_accuracy_2: count(|class| !matches!(class, TempoClass::Other | TempoClass::Unknown)),
+        tracks,
+    };
+
+    println!(
+        "{:<32} {:<8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6}",
+        "track", "beats", "F", "P", "R", "CMLc", "CMLt", "AMLc", "AMLt"
+    );
+    for track in &summary.tracks {
+        let name = track
+            .input
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        track.detector.print(&name, "detector");
+        track.tracker.print(&name, "tracker");
+        let bpm = |bpm: Option<f64>| bpm.map_or("-".to_owned(), |bpm| format!("{bpm:.1}"));
+        println!(
+            "{:<32} tempo    {} (reference {}): {:?}",
+            "",
+            bpm(track.estimated_bpm),
+            bpm(track.reference_bpm),
+            track.tempo_class
+        );
+    }
+    summary.detector.print("mean", "detector");
+    summary.tracker.print("mean", "tracker");
+    println!(
+        "tempo accuracy: {:.3} (allowing octave errors: {:.3})",
+        summary.tempo_accuracy_1, summary.tempo_accuracy_2
+    );
+
+    if let Some(path) = &evaluate_args.output {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &summary)?;
+        info!("Wrote results to {}", path.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE_S: f64 = 0.07;
+
+    /// `count` beats every `period` seconds, starting at `start`.
+    fn beats(start: f64, period: f64, count: usize) -> Vec<f64> {
+        (0..count)
+            .map(|index| start + index as f64 * period)
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn identical_beats_score_perfectly() {
+        let reference = beats(1.0, 0.5, 40);
+        let scores = BeatScores::new(&reference, &reference, TOLERANCE_S);
+        for score in [
+            scores.f_measure,
+            scores.precision,
+            scores.recall,
+            scores.cml_c,
+            scores.cml_t,
+            scores.aml_c,
+            scores.aml_t,
+        ] {
+            assert_close(score, 1.0);
+        }
+    }
+
+    #[test]
+    fn off_beat_only_counts_for_allowed_metrical_levels() {
+        let reference = beats(1.0, 0.5, 40);
+        let estimated = beats(1.25, 0.5, 39);
+        let scores = BeatScores::new(&reference, &estimated, TOLERANCE_S);
+        assert_close(scores.cml_c, 0.0);
+        assert_close(scores.cml_t, 0.0);
+        assert_close(scores.aml_c, 1.0);
+        assert_close(scores.aml_t, 1.0);
+    }
+
+    #[test]
+    fn double_tempo_counts_for_allowed_metrical_levels() {
+        let reference = beats(1.0, 0.5, 40);
+        let estimated = beats(1.0, 0.25, 79);
+        let scores = BeatScores::new(&reference, &estimated, TOLERANCE_S);
+        assert!(scores.cml_t < 0.5, "CMLt {}", scores.cml_t);
+        assert_close(scores.aml_c, 1.0);
+        assert_close(scores.aml_t, 1.0);
+    }
+
+    #[test]
+    fn extra_beats_do_not_score_above_one() {
+        let reference = beats(1.0, 0.5, 20);
+        let estimated = beats(1.0, 0.5, 40);
+        let scores = BeatScores::new(&reference, &estimated, TOLERANCE_S);
+        assert_close(scores.cml_c, 0.5);
+        assert_close(scores.cml_t, 0.5);
+    }
+
+    #[test]
+    fn f_measure_matches_beats_within_tolerance() {
+        let reference = beats(1.0, 0.5, 20);
+
+        let (f, precision, recall) = f_measure(&reference, &beats(1.05, 0.5, 20), TOLERANCE_S);
+        assert_close(f, 1.0);
+        assert_close(precision, 1.0);
+        assert_close(recall, 1.0);
+
+        let (f, _, _) = f_measure(&reference, &beats(1.1, 0.5, 20), TOLERANCE_S);
+        assert_close(f, 0.0);
+
+        let (f, precision, recall) = f_measure(&reference, &beats(1.0, 1.0, 10), TOLERANCE_S);
+        assert_close(precision, 1.0);
+        assert_close(recall, 0.5);
+        assert_close(f, 2.0 / 3.0);
+
+        assert_eq!(f_measure(&reference, &[], TOLERANCE_S), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn tempo_classes() {
+        let classify = |estimated| TempoClass::classify(Some(128.0), estimated);
+        assert_eq!(classify(Some(130.0)), TempoClass::Correct);
+        assert_eq!(classify(Some(256.0)), TempoClass::Double);
+        assert_eq!(classify(Some(64.0)), TempoClass::Half);
+        assert_eq!(classify(Some(384.0)), TempoClass::Triple);
+        assert_eq!(classify(Some(128.0 / 3.0)), TempoClass::Third);
+        assert_eq!(classify(Some(150.0)), TempoClass::Other);
+        assert_eq!(classify(None), TempoClass::Unknown);
+        assert_eq!(TempoClass::classify(None, Some(128.0)), TempoClass::Unknown);
+    }
+}
@@ -0,0 +1,230 @@
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{
+    filters::{alpha_avg::AlphaAvg, energy::Energy, filter::Filter, high_pass::HighPass},
+    ring_buffer::RingBuffer,
+};
+
+use super::beat_tracker::BeatTracker;
+
+/// How the beats are divided by the hi-hats and snares.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub enum Subdivision {
+    /// Two notes per beat, the second one possibly delayed by swing.
+    #[default]
+    Eighths,
+    /// Four notes per beat, every second one possibly delayed by swing.
+    Sixteenths,
+    /// Three evenly spaced notes per beat.
+    Triplets,
+}
+
+impl Subdivision {
+    pub fn per_beat(self) -> u32 {
+        match self {
+            Subdivision::Eighths => 2,
+            Subdivision::Sixteenths => 4,
+            Subdivision::Triplets => 3,
+        }
+    }
+}
+
+/// Measures where the off-beat onsets fall relative to the beat grid, to tell straight from
+/// swung eighths, sixteenths and triplets.
+///
+/// Hi-hats and snares are picked up as sudden rises of the energy above a few kHz, the positions
+/// of their recent onsets within the beat are kept. Onsets close to the beat give the latency of
+/// the detection relative to the grid, which is subtracted from all positions. On every beat the
+/// off-beat positions are matched against the subdivisions: triplets put onsets at both a third
+/// and two thirds of the beat, sixteenths in both halves of the beat. The swing is the mean
+/// position of the delayed notes within their pair.
+pub struct Groove {
+    high_pass: HighPass,
+    energy: Energy,
+    average: AlphaAvg,
+    armed: bool,
+
+    /// Positions of the recent onsets within the beat, NaN if unused.
+    positions: RingBuffer<f32>,
+
+    pub subdivision: Subdivision,
+    /// Ratio of the length of the first to the second note of a pair: 1 is straight, 2 is triplet
+    /// swing. Always 1 for triplets.
+    pub swing: f32,
+    /// Position of the delayed note of a pair, in pairs: 0.5 is straight.
+    swing_position: f32,
+
+    /// Index of the current subdivision within the beat.
+    pub subdivision_in_beat: u32,
+    /// Progress through the current subdivision, follows the swing.
+    pub subdivision_fract: f32,
+}
+
+impl Groove {
+    const HIGH_PASS_FQ: usize = 2000;
+    const ENERGY_WINDOW_S: f32 = 0.01;
+    /// Energy is checked every this many samples.
+    const FRAME_SIZE: u64 = 64;
+    /// Averages the energy over roughly one second at 44.1 kHz.
+    const AVERAGE_ALPHA: f32 = 0.9985;
+    /// An onset rises this far above the average energy, and the energy has to fall below
+    /// `REARM` times the average before the next one.
+    const ONSET: f32 = 3.0;
+    const REARM: f32 = 1.5;
+    /// Energies below this are silence.
+    const MIN_ENERGY: f32 = 1e-6;
+
+    const HISTORY: usize = 64;
+    /// Onsets closer than this to a beat, in beats, are on the beat.
+    const ON_BEAT: f32 = 0.125;
+    /// At least this many onsets are needed to change the estimate.
+    const MIN_ONSETS: usize = 8;
+    /// Distance up to which an onset counts for a triplet position, in beats.
+    const TRIPLET_RANGE: f32 = 1.0 / 24.0;
+    /// Share of the off-beat onsets each position of a subdivision needs.
+    const MIN_SHARE: f32 = 0.25;
+    /// Positions of the delayed note, in pairs, from straight to beyond hard swing.
+    const SWING_RANGE: std::ops::Range<f32> = 0.375..0.8;
+
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            high_pass: HighPass::new(sample_rate as usize, Self::HIGH_PASS_FQ),
+            energy: Energy::new((sample_rate * Self::ENERGY_WINDOW_S) as usize),
+            average: AlphaAvg::new(Self::AVERAGE_ALPHA),
+            armed: true,
+
+            positions: RingBuffer::new_with_default(Self::HISTORY, f32::NAN),
+
+            subdivision: Subdivision::default(),
+            swing: 1.0,
+            swing_position: 0.5,
+
+            subdivision_in_beat: 0,
+            subdivision_fract: 0.0,
+        }
+    }
+
+    /// Register the onsets of the high frequencies at their position on the grid of `tracker`.
+    pub fn on_pcm_sample(&mut self, sample_index: u64, x: f32, tracker: &dyn BeatTracker) {
+        let energy = self.energy.sample(self.high_pass.sample(x));
+        if sample_index % Self::FRAME_SIZE != 0 {
+            return;
+        }
+
+        let average = self.average.avg;
+        if self.armed && energy > Self::ONSET * average && energy > Self::MIN_ENERGY {
+            self.armed = false;
+            let beat_fract = tracker.sample_to_beat_fract(sample_index);
+            self.positions.push(beat_fract);
+        } else if energy < Self::REARM * average {
+            self.armed = true;
+        }
+        self.average.sample(energy);
+    }
+
+    /// Position relative to the closest beat, in `[-0.5, 0.5)`.
+    fn signed(position: f32) -> f32 {
+        (position + 0.5).rem_euclid(1.0) - 0.5
+    }
+
+    /// Update the estimate from the recent onsets.
+    pub fn on_beat(&mut self) {
+        let positions = self
+            .positions
+            .data
+            .iter()
+            .copied()
+            .filter(|position| !position.is_nan())
+            .collect::<Vec<_>>();
+
+        let on_beat = positions
+            .iter()
+            .map(|&position| Self::signed(position))
+            .filter(|offset| offset.abs() < Self::ON_BEAT)
+            .collect::<Vec<_>>();
+        let latency = if on_beat.len() >= Self::MIN_ONSETS {
+            on_beat.iter().sum::<f32>() / on_beat.len() as f32
+        } else {
+            0.0
+        };
+
+        let off_beat = positions
+            .iter()
+            .map(|&position| (position - latency).rem_euclid(1.0))
+            .filter(|&position| Self::signed(position).abs() >= Self::ON_BEAT)
+            .collect::<Vec<_>>();
+        if off_beat.len() < Self::MIN_ONSETS {
+            return;
+        }
+
+        let share = |is_match: &dyn Fn(f32) -> bool| {
+            off_beat
+                .iter()
+                .filter(|&&position| is_match(position))
+                .count() as f32
+                / off_beat.len() as f32
+        };
+        let near =
+            |target: f32| move |position: f32| (position - target).abs() < Self::TRIPLET_RANGE;
+        let is_triplet = share(&near(1.0 / 3.0)) >= Self::MIN_SHARE
+            && share(&near(2.0 / 3.0)) >= Self::MIN_SHARE;
+        let is_sixteenth = share(&|position| (0.1875..0.4).contains(&position)) >= Self::MIN_SHARE
+            && share(&|position| (0.6875..0.9).contains(&position)) >= Self::MIN_SHARE;
+
+        let subdivision = if is_triplet {
+            Subdivision::Triplets
+        } else if is_sixteenth {
+            Subdivision::Sixteenths
+        } else {
+            Subdivision::Eighths
+        };
+        if subdivision != self.subdivision {
+            debug!("Subdivision changed to {subdivision:?}");
+            self.subdivision = subdivision;
+        }
+
+        // Positions of the onsets within their pair of notes.
+        let pairs_per_beat = match subdivision {
+            Subdivision::Eighths => 1.0,
+            Subdivision::Sixteenths => 2.0,
+            Subdivision::Triplets => {
+                self.swing_position = 0.5;
+                self.swing = 1.0;
+                return;
+            }
+        };
+        let delayed = off_beat
+            .iter()
+            .map(|position| (position * pairs_per_beat).fract())
+            .filter(|position| Self::SWING_RANGE.contains(position))
+            .collect::<Vec<_>>();
+        if !delayed.is_empty() {
+            self.swing_position = delayed.iter().sum::<f32>() / delayed.len() as f32;
+            self.swing = self.swing_position / (1.0 - self.swing_position);
+        }
+    }
+
+    /// Follow the subdivisions of the beat at `beat_fract`.
+    pub fn on_tick(&mut self, beat_fract: f32) {
+        let (in_beat, fract) = match self.subdivision {
+            Subdivision::Triplets => {
+                let position = 3.0 * beat_fract;
+                (position.floor(), position.fract())
+            }
+            Subdivision::Eighths | Subdivision::Sixteenths => {
+                let pairs_per_beat = (self.subdivision.per_beat() / 2) as f32;
+                let position = pairs_per_beat * beat_fract;
+                let (pair, in_pair) = (position.floor(), position.fract());
+                let split = self.swing_position;
+                if in_pair < split {
+                    (2.0 * pair, in_pair / split)
+                } else {
+                    (2.0 * pair + 1.0, (in_pair - split) / (1.0 - split))
+                }
+            }
+        };
+        self.subdivision_in_beat = (in_beat as u32).min(self.subdivision.per_beat() - 1);
+        self.subdivision_fract = fract.clamp(0.0, 1.0);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+/// Median-filter harmonic-percussive separation of a magnitude spectrogram.
+///
+/// Sustained tones are smooth along time, a median over the recent frames of a frequency keeps
+/// them. Drum hits are smooth along frequency, a median over the neighbouring frequencies of a
+/// frame keeps them. Both medians are turned into soft masks which split the latest frame. The
+/// time median only looks back, so the harmonic part reacts to new tones with some delay.
+///
+/// Frames may be any number of samples apart, the time median covers the frames of a fixed
+/// duration rather than a fixed number of frames.
+pub struct Hpss {
+    /// The recent frames with the number of samples since the frame before, oldest first.
+    frames: VecDeque<(u64, Vec<f32>)>,
+    harmonic_samples: u64,
+    percussive_bins: usize,
+    sorted: Vec<f32>,
+
+    /// The percussive part of the latest frame.
+    pub percussive: Vec<f32>,
+    /// The harmonic part of the latest frame.
+    pub harmonic: Vec<f32>,
+}
+
+impl Hpss {
+    /// Sharpness of the soft masks, 2 corresponds to Wiener filtering.
+    const MASK_POWER: i32 = 2;
+    /// Length of the time median.
+    const HARMONIC_S: f32 = 0.2;
+    /// Width of the frequency median.
+    const PERCUSSIVE_FQ: f32 = 700.0;
+
+    /// Separate spectra of `num_fqs` frequencies, `bin_fq` apart, with the default medians.
+    pub fn with_defaults(num_fqs: usize, sample_rate: f32, bin_fq: f32) -> Self {
+        let percussive_bins = (Self::PERCUSSIVE_FQ / bin_fq).round() as usize;
+        Self::new(num_fqs, sample_rate, Self::HARMONIC_S, percussive_bins)
+    }
+
+    /// Separate spectra of `num_fqs` frequencies, with a median over the frames of the last
+    /// `harmonic_s` seconds and one over `percussive_bins` frequencies.
+    fn new(num_fqs: usize, sample_rate: f32, harmonic_s: f32, percussive_bins: usize) -> Self {
+        Self {
+            frames: VecDeque::new(),
+            harmonic_samples: (harmonic_s * sample_rate) as u64,
+            percussive_bins: percussive_bins.max(1),
+            sorted: Vec::with_capacity(percussive_bins),
+
+            percussive: vec![0.0; num_fqs],
+            harmonic: vec![0.0; num_fqs],
+        }
+    }
+
+    fn median(sorted: &mut [f32]) -> f32 {
+        let middle = sorted.len() / 2;
+        *sorted
+            .select_nth_unstable_by(middle, |a, b| a.partial_cmp(b).unwrap())
+            .1
+    }
+
+    /// Split the next frame of magnitudes, `samples` after the previous one, into `percussive`
+    /// and `harmonic`.
+    pub fn separate(&mut self, magnitudes: impl Iterator<Item = f32>, samples: u64) {
+        // Start from silence, as if all frames before the first one were zero.
+        if self.frames.is_empty() && samples > 0 {
+            let num_fqs = self.percussive.len();
+            let silent_frames = self.harmonic_samples / samples;
+            self.frames
+                .extend((0..silent_frames).map(|_| (samples, vec![0.0; num_fqs])));
+        }
+
+        // Drop the oldest frames until the rest fits into the time median, reusing one of them.
+        let mut duration = samples + self.frames.iter().map(|(samples, _)| samples).sum::<u64>();
+        let mut frame = None;
+        while let Some(&(oldest, _)) = self.frames.front() {
+            if duration <= self.harmonic_samples {
+                break;
+            }
+            duration -= oldest;
+            frame = self.frames.pop_front().map(|(_, frame)| frame);
+        }
+        let mut frame = frame.unwrap_or_else(|| vec![0.0; self.percussive.len()]);
+        for (value, magnitude) in frame.iter_mut().zip(magnitudes) {
+            *value = magnitude;
+        }
+        self.frames.push_back((samples, frame));
+
+        let frame = &self.frames[self.frames.len() - 1].1;
+        let half_width = self.percussive_bins / 2;
+        for (index, &magnitude) in frame.iter().enumerate() {
+            self.sorted.clear();
+            self.sorted
+                .extend(self.frames.iter().map(|(_, frame)| frame[index]));
+            let harmonic = Self::median(&mut self.sorted);
+
+            self.sorted.clear();
+            let start = index.saturating_sub(half_width);
+            let end = (index + half_width + 1).min(frame.len());
+            self.sorted.extend_from_slice(&frame[start..end]);
+            let percussive = Self::median(&mut self.sorted);
+
+            let harmonic = harmonic.powi(Self::MASK_POWER);
+            let percussive = percussive.powi(Self::MASK_POWER);
+            let total = harmonic + percussive;
+            let percussive_mask = if total > f32::EPSILON {
+                percussive / total
+            } else {
+                0.5
+            };
+            self.percussive[index] = percussive_mask * magnitude;
+            self.harmonic[index] = (1.0 - percussive_mask) * magnitude;
+        }
+    }
+}
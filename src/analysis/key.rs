@@ -0,0 +1,117 @@
+use std::fmt;
+
+use serde::Serialize;
+use tracing::debug;
+
+use super::chroma::Chroma;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Key {
+    /// Pitch class of the tonic, C = 0.
+    pub tonic: u32,
+    pub mode: Mode,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 12] = [
+            "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+        ];
+        let mode = match self.mode {
+            Mode::Major => "major",
+            Mode::Minor => "minor",
+        };
+        write!(f, "{} {mode}", NAMES[self.tonic as usize % 12])
+    }
+}
+
+/// Estimates the key from the chroma of the last few seconds, by correlating it with the
+/// Krumhansl-Kessler profiles of all 24 major and minor keys.
+pub struct KeyEstimator {
+    sample_rate: f32,
+    profile: [f32; Chroma::NUM_PITCH_CLASSES],
+
+    pub key: Key,
+    /// Correlation of the recent chroma with the profile of `key`.
+    pub confidence: f32,
+}
+
+impl KeyEstimator {
+    const MAJOR: [f32; 12] = [
+        6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+    ];
+    const MINOR: [f32; 12] = [
+        6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+    ];
+    /// Longer than most chord progressions.
+    const WINDOW_S: f32 = 20.0;
+    /// Another key has to correlate this much better to replace the current one.
+    const SWITCH_MARGIN: f32 = 0.05;
+
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            profile: [0.0; Chroma::NUM_PITCH_CLASSES],
+            key: Key {
+                tonic: 0,
+                mode: Mode::Major,
+            },
+            confidence: 0.0,
+        }
+    }
+
+    /// Pearson correlation of the recent chroma with the profile of `key`.
+    fn correlation(&self, key: Key) -> f32 {
+        let template = match key.mode {
+            Mode::Major => &Self::MAJOR,
+            Mode::Minor => &Self::MINOR,
+        };
+        let rotated = |pitch_class: usize| template[(pitch_class + 12 - key.tonic as usize) % 12];
+
+        let mean_profile = self.profile.iter().sum::<f32>() / 12.0;
+        let mean_template = template.iter().sum::<f32>() / 12.0;
+        let (mut covariance, mut profile_var, mut template_var) = (0.0, 0.0, 0.0);
+        for (pitch_class, value) in self.profile.iter().enumerate() {
+            let a = value - mean_profile;
+            let b = rotated(pitch_class) - mean_template;
+            covariance += a * b;
+            profile_var += a * a;
+            template_var += b * b;
+        }
+        if profile_var <= f32::EPSILON {
+            return 0.0;
+        }
+        covariance / (profile_var * template_var).sqrt()
+    }
+
+    /// Feed the chroma once per tick, `samples` after the previous one.
+    pub fn on_chroma(&mut self, chroma: &[f32; Chroma::NUM_PITCH_CLASSES], samples: u64) {
+        let elapsed_s = samples as f32 / self.sample_rate;
+        let alpha = 1.0 - (-elapsed_s / Self::WINDOW_S).exp();
+        for (value, next) in self.profile.iter_mut().zip(chroma) {
+            *value += alpha * (next - *value);
+        }
+
+        let (best, best_correlation) = [Mode::Major, Mode::Minor]
+            .into_iter()
+            .flat_map(|mode| (0..12).map(move |tonic| Key { tonic, mode }))
+            .map(|key| (key, self.correlation(key)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .unwrap();
+
+        let current_correlation = self.correlation(self.key);
+        if best != self.key && best_correlation > current_correlation + Self::SWITCH_MARGIN {
+            debug!("Key changed to {best}, correlation {best_correlation:.2}");
+            self.key = best;
+            self.confidence = best_correlation;
+        } else {
+            self.confidence = current_correlation;
+        }
+    }
+}
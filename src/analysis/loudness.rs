@@ -0,0 +1,200 @@
+use std::f32::consts::PI;
+
+use crate::{
+    filters::{filter::Filter, k_weighting::KWeighting},
+    ring_buffer::RingBuffer,
+};
+
+/// What the normalizer in front of the analysis follows.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum NormalizerLevel {
+    /// The peaks of the samples
+    Peak,
+    /// The short-term loudness, so that single loud transients don't duck the rest
+    Loudness,
+}
+
+/// Peak of the signal between the samples, which can exceed the samples by a few dB. Three values
+/// are interpolated between every two samples with a windowed sinc, as in ITU-R BS.1770.
+struct TruePeak {
+    history: [f32; Self::TAPS],
+    phases: [[f32; Self::TAPS]; Self::OVERSAMPLING - 1],
+}
+
+impl TruePeak {
+    const OVERSAMPLING: usize = 4;
+    const TAPS: usize = 12;
+
+    fn new() -> Self {
+        let mut phases = [[0.0; Self::TAPS]; Self::OVERSAMPLING - 1];
+        for (phase, coefficients) in phases.iter_mut().enumerate() {
+            let offset = (phase + 1) as f32 / Self::OVERSAMPLING as f32;
+            for (tap, coefficient) in coefficients.iter_mut().enumerate() {
+                // Distance from the interpolated position between the two center taps.
+                let t = tap as f32 - (Self::TAPS / 2 - 1) as f32 - offset;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t).sin() / (PI * t)
+                };
+                let window = 0.5 * (1.0 + (PI * t / (Self::TAPS / 2) as f32).cos());
+                *coefficient = sinc * window;
+            }
+            let sum = coefficients.iter().sum::<f32>();
+            coefficients.iter_mut().for_each(|x| *x /= sum);
+        }
+        Self {
+            history: [0.0; Self::TAPS],
+            phases,
+        }
+    }
+
+    /// Absolute peak from the previous interpolated section up to `x`, delayed by a few samples.
+    fn sample(&mut self, x: f32) -> f32 {
+        self.history.copy_within(1.., 0);
+        self.history[Self::TAPS - 1] = x;
+
+        let center = self.history[Self::TAPS / 2].abs();
+        self.phases
+            .iter()
+            .map(|coefficients| {
+                let interpolated = coefficients.iter().zip(&self.history);
+                interpolated.map(|(c, x)| c * x).sum::<f32>().abs()
+            })
+            .fold(center, f32::max)
+    }
+}
+
+struct Channel {
+    k_weighting: KWeighting,
+    true_peak: TruePeak,
+    /// Sums over the current block.
+    weighted_energy: f32,
+    energy: f32,
+    peak: f32,
+}
+
+impl Channel {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            k_weighting: KWeighting::new(sample_rate),
+            true_peak: TruePeak::new(),
+            weighted_energy: 0.0,
+            energy: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    fn sample(&mut self, x: f32) {
+        self.weighted_energy += self.k_weighting.sample(x).powi(2);
+        self.energy += x * x;
+        self.peak = self.peak.max(self.true_peak.sample(x));
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Block {
+    /// Mean square of the K-weighted signal, summed over the channels.
+    weighted: f32,
+    mean_square: [f32; 2],
+    peak: [f32; 2],
+}
+
+/// Level meter of the stereo input before normalization, with the momentary (400 ms) and
+/// short-term (3 s) loudness of EBU R 128. Levels are measured in blocks of 100 ms, all values are
+/// updated once per block.
+pub struct Loudness {
+    channels: [Channel; 2],
+    block_size: usize,
+    block_samples: usize,
+    blocks: RingBuffer<Block>,
+    /// Oldest block first.
+    recent_blocks: Vec<Block>,
+
+    /// RMS of the left and right channel over the momentary window, in dBFS.
+    pub rms_db: [f32; 2],
+    /// Peaks of the left and right channel over the momentary window, in dBTP.
+    pub true_peak_db: [f32; 2],
+    /// Loudness of the last 400 ms in LUFS.
+    pub momentary_lufs: f32,
+    /// Loudness of the last 3 s in LUFS.
+    pub short_term_lufs: f32,
+}
+
+impl Loudness {
+    const BLOCK_S: f32 = 0.1;
+    const MOMENTARY_BLOCKS: usize = 4;
+    const SHORT_TERM_BLOCKS: usize = 30;
+    /// Reported for silence, the absolute gate of EBU R 128.
+    const FLOOR_DB: f32 = -70.0;
+
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            channels: [Channel::new(sample_rate), Channel::new(sample_rate)],
+            block_size: (Self::BLOCK_S * sample_rate) as usize,
+            block_samples: 0,
+            blocks: RingBuffer::new(Self::SHORT_TERM_BLOCKS),
+            recent_blocks: vec![Block::default(); Self::SHORT_TERM_BLOCKS],
+
+            rms_db: [Self::FLOOR_DB; 2],
+            true_peak_db: [Self::FLOOR_DB; 2],
+            momentary_lufs: Self::FLOOR_DB,
+            short_term_lufs: Self::FLOOR_DB,
+        }
+    }
+
+    /// Feed the raw samples of both channels.
+    pub fn on_pcm_sample(&mut self, samples: [f32; 2]) {
+        for (channel, x) in self.channels.iter_mut().zip(samples) {
+            channel.sample(x);
+        }
+        self.block_samples += 1;
+        if self.block_samples == self.block_size {
+            self.on_block();
+        }
+    }
+
+    fn on_block(&mut self) {
+        let size = self.block_samples as f32;
+        let mut block = Block::default();
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            block.weighted += channel.weighted_energy / size;
+            block.mean_square[index] = channel.energy / size;
+            block.peak[index] = channel.peak;
+            (channel.weighted_energy, channel.energy, channel.peak) = (0.0, 0.0, 0.0);
+        }
+        self.block_samples = 0;
+        self.blocks.push(block);
+
+        self.blocks
+            .write_to_buffer(Self::SHORT_TERM_BLOCKS, &mut self.recent_blocks);
+        let short_term = &self.recent_blocks;
+        let momentary = &short_term[Self::SHORT_TERM_BLOCKS - Self::MOMENTARY_BLOCKS..];
+
+        let mean = |blocks: &[Block], value: fn(&Block) -> f32| {
+            blocks.iter().map(value).sum::<f32>() / blocks.len() as f32
+        };
+        self.momentary_lufs = Self::lufs(mean(momentary, |block| block.weighted));
+        self.short_term_lufs = Self::lufs(mean(short_term, |block| block.weighted));
+        for index in 0..2 {
+            let mean_square = momentary.iter().map(|block| block.mean_square[index]);
+            let peak = momentary.iter().map(|block| block.peak[index]);
+            self.rms_db[index] = Self::db(mean_square.sum::<f32>() / momentary.len() as f32, 10.0);
+            self.true_peak_db[index] = Self::db(peak.fold(0.0, f32::max), 20.0);
+        }
+    }
+
+    fn db(value: f32, factor: f32) -> f32 {
+        (factor * value.max(f32::MIN_POSITIVE).log10()).max(Self::FLOOR_DB)
+    }
+
+    fn lufs(weighted: f32) -> f32 {
+        (-0.691 + Self::db(weighted, 10.0)).max(Self::FLOOR_DB)
+    }
+
+    /// Short-term loudness as the RMS amplitude of a single channel.
+    pub fn short_term_amplitude(&self) -> f32 {
+        let mean_square = 10f32.powf((self.short_term_lufs + 0.691) / 10.0) / 2.0;
+        mean_square.sqrt()
+    }
+}
@@ -0,0 +1,132 @@
+use tracing::{info, warn};
+
+use crate::Args;
+
+use super::beat_tracker::BeatTracker;
+
+/// Keyboard overrides of the beat grid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManualAction {
+    /// Tap along with the beat, sets the phase and from the second tap on the BPM.
+    Tap,
+    /// Move the grid earlier.
+    NudgeForward,
+    /// Move the grid later.
+    NudgeBack,
+    HalveTempo,
+    DoubleTempo,
+    /// Stop or resume feeding the onsets to the tracker, the grid keeps running while locked.
+    ToggleLock,
+}
+
+/// Tap tempo and nudging of the grid of a `BeatTracker`.
+///
+/// Every action sets the grid of the tracker, which continues from there once tracking is
+/// unlocked. Tapping locks the tracking, otherwise the next onsets would pull the grid back to
+/// what was tracked before.
+pub struct ManualControl {
+    sample_rate: f32,
+    slowest_bpm: f32,
+    fastest_bpm: f32,
+    /// The taps of the current series, oldest first.
+    taps: Vec<u64>,
+    pub locked: bool,
+}
+
+impl ManualControl {
+    /// A pause this long starts a new series of taps.
+    const TAP_TIMEOUT_S: f32 = 2.0;
+    /// The BPM is averaged over at most this many taps.
+    const MAX_TAPS: usize = 8;
+    const NUDGE_S: f32 = 0.01;
+
+    pub fn new(args: &Args, sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            slowest_bpm: args.slowest_bpm as f32,
+            fastest_bpm: args.fastest_bpm as f32,
+            taps: Vec::with_capacity(Self::MAX_TAPS),
+            locked: false,
+        }
+    }
+
+    /// The most recent beat of the grid at `sample_index`.
+    fn last_beat(&self, tracker: &dyn BeatTracker, sample_index: u64) -> u64 {
+        let fract = tracker.sample_to_beat_fract(sample_index);
+        let period_samples = tracker.bpm().period * self.sample_rate;
+        sample_index.saturating_sub((fract * period_samples) as u64)
+    }
+
+    fn tap(&mut self, tracker: &mut dyn BeatTracker, sample_index: u64) {
+        let timeout = (Self::TAP_TIMEOUT_S * self.sample_rate) as u64;
+        if let Some(&last) = self.taps.last() {
+            if sample_index.saturating_sub(last) > timeout {
+                self.taps.clear();
+            }
+        }
+        if self.taps.len() == Self::MAX_TAPS {
+            self.taps.remove(0);
+        }
+        self.taps.push(sample_index);
+
+        let bpm = match self.taps.as_slice() {
+            [first, .., last] => {
+                let period_s =
+                    (last - first) as f32 / self.sample_rate / (self.taps.len() - 1) as f32;
+                60.0 / period_s
+            }
+            _ => tracker.bpm().value,
+        };
+        tracker.set_grid(bpm, sample_index);
+        self.locked = true;
+        info!("Tapped {bpm:.1} BPM");
+    }
+
+    /// Set the grid to `factor` times the current tempo, unless that leaves the tracked range.
+    fn scale_tempo(&self, factor: f32, tracker: &mut dyn BeatTracker, last_beat: u64) {
+        let bpm = tracker.bpm().value;
+        let scaled = factor * bpm;
+        if (self.slowest_bpm..=self.fastest_bpm).contains(&scaled) {
+            tracker.set_grid(scaled, last_beat);
+            info!("Changed the tempo from {bpm:.1} to {scaled:.1} BPM");
+        } else {
+            warn!(
+                "Keeping {bpm:.1} BPM, {scaled:.1} BPM is outside of {}-{} BPM",
+                self.slowest_bpm, self.fastest_bpm
+            );
+        }
+    }
+
+    /// Continue a locked grid of `old` on `new`, which replaces it when the analysis is reset.
+    pub fn carry_over(&self, old: &dyn BeatTracker, new: &mut dyn BeatTracker, sample_index: u64) {
+        if self.locked {
+            new.set_grid(old.bpm().value, self.last_beat(old, sample_index));
+        }
+    }
+
+    /// Apply `action` to the grid of `tracker`, `sample_index` is the latest sample.
+    pub fn apply(
+        &mut self,
+        action: ManualAction,
+        tracker: &mut dyn BeatTracker,
+        sample_index: u64,
+    ) {
+        let bpm = tracker.bpm().value;
+        let last_beat = self.last_beat(tracker, sample_index);
+        let nudge = (Self::NUDGE_S * self.sample_rate) as u64;
+        match action {
+            ManualAction::Tap => self.tap(tracker, sample_index),
+            ManualAction::NudgeForward => tracker.set_grid(bpm, last_beat.saturating_sub(nudge)),
+            ManualAction::NudgeBack => tracker.set_grid(bpm, last_beat + nudge),
+            ManualAction::HalveTempo => self.scale_tempo(0.5, tracker, last_beat),
+            ManualAction::DoubleTempo => self.scale_tempo(2.0, tracker, last_beat),
+            ManualAction::ToggleLock => {
+                self.locked = !self.locked;
+                info!(
+                    "{} automatic beat tracking at {bpm:.1} BPM",
+                    if self.locked { "Locked" } else { "Unlocked" }
+                );
+            }
+        }
+    }
+}
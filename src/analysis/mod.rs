@@ -1,44 +1,114 @@
+pub mod agent_tracker;
+pub mod bar_tracker;
+pub mod bass_band;
 pub mod beat_detector;
+pub mod beat_tracker;
 pub mod bpm_tracker;
+pub mod chroma;
+pub mod clock;
+pub mod descriptors;
 pub mod dft;
+pub mod evaluation;
+pub mod groove;
+pub mod hpss;
+pub mod key;
+pub mod loudness;
+pub mod manual;
+pub mod offline;
+pub mod onset_detector;
+pub mod percussion;
+pub mod pitch;
+pub mod prediction;
+pub mod section;
 pub mod server;
+pub mod spectral_flux;
+pub mod tempogram;
+pub mod track_monitor;
 
-use std::{
-    sync::Arc,
-    time::{self, Instant},
-};
+use std::{sync::Arc, time::Duration};
 
-use beat_detector::BeatDetector;
-use bpm_tracker::BpmTracker;
+use bar_tracker::BarTracker;
+use beat_tracker::BeatTracker;
+use chroma::Chroma;
+use clock::{Clock, Pacing, WallClock};
+use descriptors::SpectralDescriptors;
 use dft::Dft;
+use groove::Groove;
+use hpss::Hpss;
+use key::KeyEstimator;
+use loudness::{Loudness, NormalizerLevel};
+use manual::{ManualAction, ManualControl};
+use onset_detector::OnsetDetector;
+use percussion::Percussion;
+use pitch::PitchTracker;
+use prediction::PredictedBeat;
+use section::{Section, SectionTracker};
 use server::FrameSender;
+use tempogram::Tempogram;
+use track_monitor::{TrackEvent, TrackMonitor};
 
 use crate::{
-    filters::{filter::Filter, max_decay_normalizer::MaxDecayNormalizer},
+    audio::stereo::Stereo,
+    filters::{agc::Agc, filter::Filter, max_decay_normalizer::MaxDecayNormalizer},
     ring_buffer::RingBuffer,
     Args,
 };
 
+/// The filter in front of the analysis, which removes the overall level of the input.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Normalizer {
+    /// Automatic gain control with attack, release and lookahead
+    Agc,
+    /// Divide by the decaying maximum, distorts pure tones slightly
+    MaxDecay,
+}
+
 /// Note the reverse drop order.
 pub struct Analysis {
+    args: Args,
     sample_rate: f32,
     pub buf_size: usize,
 
-    pub epoch: time::Instant,
-    last_tick: time::Instant,
+    clock: Box<dyn Clock>,
+    pacing: Pacing,
+    last_tick: Duration,
     pub sample_index: u64,
+    tick_start_sample: u64,
 
     pub tick_start_index: usize,
     pub tick_end_index: usize,
 
+    agc: Agc,
     normalizer: MaxDecayNormalizer,
+    /// Levels of both channels before normalization.
+    pub loudness: Loudness,
 
     signal: RingBuffer<f32>,
     pub bass_energy: RingBuffer<f32>,
     pub signal_dft: Dft,
-
-    pub beat_detector: BeatDetector,
-    pub bpm_tracker: BpmTracker,
+    /// Separates the spectrum of every tick, the harmonic part is passed on to the shaders.
+    pub hpss: Hpss,
+    /// Pitch classes of the harmonic part, the key is estimated from them.
+    pub chroma: Chroma,
+    pub key: KeyEstimator,
+    /// Fundamental frequency of the lead.
+    pub pitch: PitchTracker,
+    /// Brightness, noisiness and change of the spectrum.
+    pub descriptors: SpectralDescriptors,
+
+    pub beat_detector: Box<dyn OnsetDetector>,
+    pub bpm_tracker: Box<dyn BeatTracker>,
+    /// Tap tempo and nudging from the keyboard, may lock the tracker.
+    pub manual: ManualControl,
+    pub tempogram: Tempogram,
+    pub bar_tracker: BarTracker,
+    pub percussion: Percussion,
+    /// Swing and subdivision of the beat, from the onsets of the high frequencies.
+    pub groove: Groove,
+    pub sections: SectionTracker,
+    pub track_monitor: TrackMonitor,
+    /// The track changed or the signal returned, the analysis is reset when the next tick begins.
+    pending_reset: bool,
 
     pub beat_in_tick: bool,
     pub real_beats: u32,
@@ -46,11 +116,39 @@ pub struct Analysis {
     pub fake_beats: u32,
     pub beat_fract: f32,
 
+    /// Time from the last consumed sample until a frame rendered now is on screen.
+    display_latency_s: f32,
+    /// The beat grid at the time the current frame is on screen.
+    pub predicted: PredictedBeat,
+
     broadcast: Option<Arc<FrameSender>>,
 }
 
 impl Analysis {
+    /// Consume a few samples more than the elapsed time corresponds to. Otherwise rounding down
+    /// lets the analysis fall behind the input.
+    const CATCH_UP_SAMPLES: usize = 5;
+    /// Typical crest factor of music, normalizing to the loudness puts the peaks around 1.
+    const LOUDNESS_HEADROOM: f32 = 4.0;
+    /// Peaks may exceed 1 by this much when normalizing to the loudness. Before the loudness caught
+    /// up with a sudden rise, e.g. after silence, the peaks are followed instead.
+    const LOUDNESS_CEILING: f32 = 2.0;
+    /// Short against the lookahead, the gain has settled when a transient leaves the AGC.
+    const AGC_ATTACK_S: f32 = 0.001;
+    const AGC_RELEASE_S: f32 = 6.0;
+    /// Delays the whole analysis, keep it short.
+    const AGC_LOOKAHEAD_S: f32 = 0.005;
+
     pub fn new(args: &Args, sample_rate: f32, broadcast: Option<Arc<FrameSender>>) -> Self {
+        Self::with_clock(args, sample_rate, broadcast, Box::new(WallClock::new()))
+    }
+
+    pub fn with_clock(
+        args: &Args,
+        sample_rate: f32,
+        broadcast: Option<Arc<FrameSender>>,
+        clock: Box<dyn Clock>,
+    ) -> Self {
         let audio_buffer_size = (args.audio_buffer_sec * sample_rate) as usize;
 
         let dft_size = args.dft_size;
@@ -67,25 +165,63 @@ impl Analysis {
         // let beat_dft_lower = dft_index_of_frequency(35, audio.sample_rate(), dft_size);
         // let beat_dft_upper = dft_index_of_frequency(125, audio.sample_rate(), dft_size);
 
+        // Non-realtime inputs are fed once per tick, consume all of it.
+        let has_source = args.input_file.is_some() || args.generator.generate.is_some();
+        let pacing = args.pacing.unwrap_or(if has_source && args.no_realtime {
+            Pacing::Available
+        } else {
+            Pacing::Elapsed
+        });
+
+        let signal_dft = Dft::new(dft_size, sample_rate);
+        let bin_fq = sample_rate / dft_size as f32;
+        let hpss = Hpss::with_defaults(dft_size / 2 + 1, sample_rate, bin_fq);
+        let bar_tracker = BarTracker::new(args, signal_dft.num_log_bins());
+        let track_monitor = TrackMonitor::new(args, sample_rate, signal_dft.num_log_bins());
+
         Self {
+            args: args.clone(),
             sample_rate,
             buf_size: audio_buffer_size,
 
-            epoch: Instant::now(),
-            last_tick: Instant::now(),
+            last_tick: clock.elapsed(),
+            clock,
+            pacing,
             sample_index: 0,
+            tick_start_sample: 0,
 
             tick_start_index: 0,
             tick_end_index: 0,
 
+            agc: Agc::new(
+                sample_rate,
+                args.agc_detector,
+                Self::AGC_ATTACK_S,
+                Self::AGC_RELEASE_S,
+                Self::AGC_LOOKAHEAD_S,
+            ),
             normalizer: MaxDecayNormalizer::new(0.999997, 0.05),
+            loudness: Loudness::new(sample_rate),
 
             signal: RingBuffer::new(audio_buffer_size),
             bass_energy: RingBuffer::new(audio_buffer_size),
-            signal_dft: Dft::new(dft_size, sample_rate),
-
-            beat_detector: BeatDetector::new(args, sample_rate),
-            bpm_tracker: BpmTracker::new(args, sample_rate),
+            signal_dft,
+            hpss,
+            chroma: Chroma::new(dft_size, sample_rate),
+            key: KeyEstimator::new(sample_rate),
+            pitch: PitchTracker::new(sample_rate),
+            descriptors: SpectralDescriptors::new(dft_size, sample_rate),
+
+            beat_detector: onset_detector::new_onset_detector(args, sample_rate),
+            bpm_tracker: beat_tracker::new_beat_tracker(args, sample_rate),
+            manual: ManualControl::new(args, sample_rate),
+            tempogram: Tempogram::new(args, sample_rate),
+            bar_tracker,
+            percussion: Percussion::new(args, sample_rate),
+            groove: Groove::new(sample_rate),
+            sections: SectionTracker::new(sample_rate),
+            track_monitor,
+            pending_reset: false,
 
             beat_in_tick: false,
             real_beats: 0,
@@ -93,6 +229,9 @@ impl Analysis {
             fake_beats: 0,
             beat_fract: 0.0,
 
+            display_latency_s: args.display_latency_ms.map_or(0.0, |ms| ms / 1000.0),
+            predicted: PredictedBeat::default(),
+
             // low_pass: LowPass::new(sample_rate, 100),
             // low_pass_buffer: RingBuffer::new(audio_buffer_size),
             // low_pass_dft: Dft::new(args.dft_size),
@@ -105,7 +244,7 @@ impl Analysis {
 
     /// Compute the read index (start of data to read), write index (index at which new data will
     /// be written (end of data to read).
-    fn update_slice_indices(&mut self, signal: &RingBuffer<f32>, delta: f32) {
+    fn update_slice_indices(&mut self, signal: &RingBuffer<f32>, delta: Duration) {
         // Requiring self.signal and signal to be of same size.
         let index_first_new = self.signal.write_index;
         let index_last_new = signal.write_index;
@@ -118,88 +257,252 @@ impl Analysis {
         };
 
         // I want to consume this much!
-        let mut consume_samples = (self.sample_rate * delta) as usize + 5;
+        let consume_samples = match self.pacing {
+            Pacing::Elapsed => {
+                (self.sample_rate * delta.as_secs_f32()) as usize + Self::CATCH_UP_SAMPLES
+            }
+            Pacing::Available => available_samples,
+            Pacing::Fixed(step) => step,
+        };
 
-        if consume_samples > available_samples {
-            // Don't care about underruns...
-            // let underrun = consume_samples - available_samples;
-            // warn!("Sample underrun by {underrun}");
-            consume_samples = available_samples;
-        }
+        // Don't care about underruns...
+        // let underrun = consume_samples - available_samples;
+        // warn!("Sample underrun by {underrun}");
+        let consume_samples = consume_samples.min(available_samples);
 
         self.tick_start_index = index_first_new;
         self.tick_end_index = (index_first_new + consume_samples) % self.buf_size;
     }
 
-    fn on_pcm_sample(&mut self, x: f32) {
+    /// Forget everything learned about the music, the next track may be entirely different.
+    fn reset(&mut self) {
+        let (args, sample_rate) = (&self.args, self.sample_rate);
+        self.agc.reset();
+        self.normalizer.reset();
+        self.beat_detector = onset_detector::new_onset_detector(args, sample_rate);
+        // A locked grid stays, the new tracker continues it.
+        let bpm_tracker = beat_tracker::new_beat_tracker(args, sample_rate);
+        let old_tracker = std::mem::replace(&mut self.bpm_tracker, bpm_tracker);
+        self.manual.carry_over(
+            old_tracker.as_ref(),
+            self.bpm_tracker.as_mut(),
+            self.sample_index,
+        );
+        self.tempogram = Tempogram::new(args, sample_rate);
+        self.bar_tracker = BarTracker::new(args, self.signal_dft.num_log_bins());
+        self.percussion = Percussion::new(args, sample_rate);
+        self.groove = Groove::new(sample_rate);
+        self.sections = SectionTracker::new(sample_rate);
+        self.key = KeyEstimator::new(sample_rate);
+    }
+
+    /// Only the left channel is analyzed, the right one is just metered.
+    fn on_pcm_sample(&mut self, samples: [f32; 2]) {
         self.sample_index += 1;
+        let x = samples[0];
+        self.loudness.on_pcm_sample(samples);
+
+        // The level of the input is only known before normalization.
+        let event = self.track_monitor.on_pcm_sample(self.sample_index, x);
+        if event == Some(TrackEvent::SignalReturned) {
+            self.pending_reset = true;
+        }
 
-        // The normalizer sucks. (introduces light saw wave to pure signals.)
-        // TODO replace with something better.
-        let x = self.normalizer.sample(x);
+        let raw = x;
+        let x = match (self.args.normalizer, self.args.normalize_to) {
+            (Normalizer::Agc, _) => self.agc.sample(x),
+            (Normalizer::MaxDecay, NormalizerLevel::Peak) => self.normalizer.sample(x),
+            (Normalizer::MaxDecay, NormalizerLevel::Loudness) => {
+                let loudness = Self::LOUDNESS_HEADROOM * self.loudness.short_term_amplitude();
+                let level = loudness.max(x.abs() / Self::LOUDNESS_CEILING);
+                self.normalizer.normalize_to(x, level)
+            }
+        };
         self.signal.push(x);
 
         let (bass_energy, is_beat) = self.beat_detector.on_pcm_sample(self.sample_index, x);
         self.bass_energy.push(bass_energy);
+        let estimate = self.tempogram.on_pcm_sample(self.sample_index, bass_energy);
+        // While locked the grid runs on at the manual tempo and phase.
+        let locked = self.manual.locked;
+        if let Some(estimate) = estimate.filter(|_| !locked) {
+            self.bpm_tracker.on_tempo_estimate(estimate);
+        }
         if  is_beat {
             self.real_beats += 1;
             self.beat_in_tick = true;
-            self.bpm_tracker.on_beat(self.sample_index);
+            if !locked {
+                self.bpm_tracker.on_beat(self.sample_index);
+            }
         }
+        self.percussion.on_pcm_sample(self.sample_index, x);
+        self.groove
+            .on_pcm_sample(self.sample_index, x, self.bpm_tracker.as_ref());
+        self.bar_tracker
+            .on_pcm_sample(self.percussion.kick.energy());
+        self.sections.on_pcm_sample(self.sample_index, raw);
+        self.pitch.on_pcm_sample(x);
+        self.descriptors.on_pcm_sample(x);
 
         // Every 128th PCM sample.
         if self.sample_index & 0b1111111 == 0 {
             let to_float = |x: bool| if x { 1.0 } else { 0.0 };
             if let Some(broadcast) = &self.broadcast {
+                let [energy, short, long] = self.beat_detector.debug_values();
+                let [kick, snare, hihat] = self.percussion.energies();
+                let [is_kick, is_snare, is_hihat] = self.percussion.onsets();
+                let bars = &self.bar_tracker;
+                let [phrase_8, phrase_16, phrase_32] = bars.phrases.map(|phrase| phrase.bar as f32);
                 broadcast
                     .send(vec![
-                        self.beat_detector.stats.energy,
-                        self.beat_detector.stats.short.avg,
-                        self.beat_detector.stats.long.avg,
+                        energy,
+                        short,
+                        long,
                         to_float(self.beat_in_tick),
                         self.bpm_tracker.beat_probability(self.sample_index),
-                        self.bpm_tracker.phase_error / 50.0 + 0.5,
+                        self.bpm_tracker.phase_error() / 50.0 + 0.5,
+                        kick,
+                        snare,
+                        hihat,
+                        to_float(is_kick),
+                        to_float(is_snare),
+                        to_float(is_hihat),
+                        bars.beat_in_bar as f32,
+                        bars.bar_index as f32,
+                        bars.downbeat_confidence,
+                        phrase_8,
+                        phrase_16,
+                        phrase_32,
+                        bars.phrases[0].confidence,
+                        bars.beats_per_bar as f32,
+                        self.sections.section as u32 as f32,
+                        self.sections.build_up_progress,
+                        to_float(self.track_monitor.no_signal),
+                        self.groove.subdivision as u32 as f32,
+                        self.groove.swing,
+                        self.groove.subdivision_fract,
+                        self.key.key.tonic as f32,
+                        self.key.key.mode as u32 as f32,
+                        self.key.confidence,
+                        self.pitch.fq,
+                        self.pitch.note,
+                        self.pitch.confidence,
+                        self.descriptors.centroid,
+                        self.descriptors.spread,
+                        self.descriptors.rolloff,
+                        self.descriptors.flatness,
+                        self.descriptors.flux,
+                        self.descriptors.zero_crossing_rate,
+                        self.loudness.rms_db[0],
+                        self.loudness.rms_db[1],
+                        self.loudness.true_peak_db[0],
+                        self.loudness.true_peak_db[1],
+                        self.loudness.momentary_lufs,
+                        self.loudness.short_term_lufs,
                     ])
                     .expect("Failed to broadcast frame bass frequencies");
             }
         }
     }
 
+    fn begin_tick(&mut self) {
+        // Reset between ticks, so that no tick mixes the state of the old and the new trackers.
+        if std::mem::take(&mut self.pending_reset) {
+            self.reset();
+        }
+        self.beat_in_tick = false;
+        self.percussion.begin_tick();
+        self.sections.begin_tick();
+        self.tick_start_sample = self.sample_index;
+    }
+
+    fn end_tick(&mut self) {
+        // Count bpm beats by checking whether the beat fract wrapped around in this tick.
+        let fract_pre = self.beat_fract;
+        self.beat_fract = self.bpm_tracker.sample_to_beat_fract(self.sample_index);
+        if self.beat_fract < 0.1 && fract_pre > 0.9 {
+            self.fake_beats += 1;
+            self.bar_tracker.on_beat();
+            self.bpm_tracker
+                .set_beats_per_bar(self.bar_tracker.beats_per_bar);
+            self.groove.on_beat();
+        }
+        self.predicted.update(
+            self.bpm_tracker.as_ref(),
+            self.sample_index,
+            self.sample_rate,
+            self.display_latency_s,
+            self.fake_beats,
+        );
+        self.groove.on_tick(self.predicted.beat_fract);
+
+        // Run DFTs on filtered/split signals.
+        let samples_since_last_multiple_of_dft = self.sample_index & 0b11111111;
+        let offset_from_end = samples_since_last_multiple_of_dft as usize + self.signal_dft.size();
+        let dft_vec = self.signal_dft.get_input_vec();
+        self.signal.write_to_buffer(offset_from_end, dft_vec);
+        self.signal_dft.run_transform();
+        let samples = self.sample_index - self.tick_start_sample;
+        self.hpss.separate(self.signal_dft.magnitudes(), samples);
+        self.chroma.on_spectrum(&self.hpss.harmonic);
+        self.key.on_chroma(&self.chroma.chroma, samples);
+        self.pitch.on_tick();
+        self.bar_tracker.on_tick(self.signal_dft.log_bins());
+        self.descriptors.on_spectrum(self.signal_dft.magnitudes());
+        self.sections.on_centroid(self.descriptors.centroid);
+
+        let kick_is_playing = self.sections.section == Section::Steady;
+        let spectrum = self.signal_dft.log_bins();
+        let event = self
+            .track_monitor
+            .on_spectrum(spectrum, samples, kick_is_playing);
+        if event == Some(TrackEvent::TrackChanged) {
+            self.pending_reset = true;
+        }
+    }
+
     // A tick is @ 60Hz / or so i think...
     // A sample is @ 44100Hz
     // A frame is @ 44100Hz / 64 == 689.0625Hz
-    pub fn on_tick(&mut self, signal: &RingBuffer<f32>) {
-        let delta = self.last_tick.elapsed().as_secs_f32();
-        self.last_tick = Instant::now();
+    /// Time of the analysis in seconds, according to its clock.
+    pub fn time(&self) -> f32 {
+        self.clock.elapsed().as_secs_f32()
+    }
 
-        self.beat_in_tick = false;
-        let fract_pre = self.beat_fract;
+    pub fn on_manual_action(&mut self, action: ManualAction) {
+        self.manual
+            .apply(action, self.bpm_tracker.as_mut(), self.sample_index);
+    }
+
+    /// Use the display latency estimated by the visualizer, unless it was given on the command
+    /// line.
+    pub fn set_measured_display_latency(&mut self, latency_s: f32) {
+        if self.args.display_latency_ms.is_none() {
+            self.display_latency_s = latency_s;
+        }
+    }
+
+    pub fn on_tick(&mut self, stereo: &Stereo) {
+        let now = self.clock.elapsed();
+        let delta = now.saturating_sub(self.last_tick);
+        self.last_tick = now;
+
+        self.begin_tick();
 
         // Run sample-by-sample analysis.
-        self.update_slice_indices(signal, delta);
+        self.update_slice_indices(&stereo.signal, delta);
         let start = self.tick_start_index;
         let end = self.tick_end_index;
         if end < start {
             for index in (start..self.buf_size).chain(0..end) {
-                self.on_pcm_sample(signal.data[index]);
+                self.on_pcm_sample([stereo.left.data[index], stereo.right.data[index]]);
             }
         } else {
             for index in start..end {
-                self.on_pcm_sample(signal.data[index]);
+                self.on_pcm_sample([stereo.left.data[index], stereo.right.data[index]]);
             }
         }
 
-        // Count bpm beats by checking whether the beat fract wrapped around in this tick.
-        self.beat_fract = self.bpm_tracker.sample_to_beat_fract(self.sample_index);
-        if self.beat_fract < 0.1 && fract_pre > 0.9 {
-            self.fake_beats += 1;
-        }
-
-        // Run DFTs on filtered/split signals.
-        let samples_since_last_multiple_of_dft = self.sample_index & 0b11111111;
-        let offset_from_end = samples_since_last_multiple_of_dft as usize + self.signal_dft.size();
-        let dft_vec = self.signal_dft.get_input_vec();
-        self.signal.write_to_buffer(offset_from_end, dft_vec);
-        self.signal_dft.run_transform();
+        self.end_tick();
     }
 }
@@ -0,0 +1,428 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use serde::Serialize;
+use tracing::{info, instrument};
+
+use crate::{
+    audio::{file::AudioFile, generator::Generator},
+    error::{Error, VResult},
+    Args,
+};
+
+use super::{
+    beat_tracker::BeatHypothesis, clock::ManualClock, groove::Subdivision, section::Section,
+    tempogram::TempoCandidate, Analysis,
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+/// Run the analysis over an entire audio file and write a report.
+#[derive(clap::Args, Debug, Clone)]
+pub struct AnalyzeArgs {
+    /// The audio file to analyze (WAV or FLAC), omit when using `--generate`
+    pub input: Option<PathBuf>,
+
+    /// How many seconds of the generated signal to analyze
+    #[arg(long, default_value = "60")]
+    pub duration_s: f32,
+
+    /// Where to write the report, defaults to stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// The report format
+    #[arg(short, long, value_enum, default_value = "json")]
+    pub format: ReportFormat,
+}
+
+/// Snapshot of the tracker state at a given sample.
+#[derive(Serialize)]
+pub struct Record {
+    pub sample_index: u64,
+    pub time: f64,
+    pub bpm: f32,
+    pub period: f32,
+    pub beat_fract: f32,
+    pub phase: f32,
+    pub phase_error: f32,
+    pub bpm_confidence: f32,
+    pub beats_per_bar: u32,
+    pub beat_in_bar: u32,
+    pub bar_index: u32,
+    pub downbeat_confidence: f32,
+    pub phrase_8_bar: u32,
+    pub section: Section,
+    pub build_up_progress: f32,
+    pub no_signal: bool,
+    pub subdivision: Subdivision,
+    pub swing: f32,
+    pub subdivision_fract: f32,
+    /// The tempo hypotheses of the autocorrelation, best first.
+    pub tempo_candidates: Vec<TempoCandidate>,
+    /// The hypotheses of the beat tracker, the followed one first.
+    pub beat_hypotheses: Vec<BeatHypothesis>,
+}
+
+impl Record {
+    fn new(analysis: &Analysis, beat_fract: f32) -> Self {
+        let tracker = &analysis.bpm_tracker;
+        Record {
+            sample_index: analysis.sample_index,
+            time: analysis.sample_index as f64 / analysis.sample_rate as f64,
+            bpm: tracker.bpm().value,
+            period: tracker.bpm().period,
+            beat_fract,
+            phase: tracker.phase_offset(),
+            phase_error: tracker.phase_error(),
+            bpm_confidence: tracker.bpm_confidence(),
+            beats_per_bar: analysis.bar_tracker.beats_per_bar,
+            beat_in_bar: analysis.bar_tracker.beat_in_bar,
+            bar_index: analysis.bar_tracker.bar_index,
+            downbeat_confidence: analysis.bar_tracker.downbeat_confidence,
+            phrase_8_bar: analysis.bar_tracker.phrases[0].bar,
+            section: analysis.sections.section,
+            build_up_progress: analysis.sections.build_up_progress,
+            no_signal: analysis.track_monitor.no_signal,
+            subdivision: analysis.groove.subdivision,
+            swing: analysis.groove.swing,
+            subdivision_fract: analysis.groove.subdivision_fract,
+            tempo_candidates: analysis
+                .tempogram
+                .estimate
+                .as_ref()
+                .map(|estimate| estimate.candidates.clone())
+                .unwrap_or_default(),
+            beat_hypotheses: tracker.hypotheses(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub input: String,
+    pub sample_rate: f32,
+    pub num_samples: u64,
+    /// One record per detected beat, taken right after the tracker processed it.
+    pub beats: Vec<Record>,
+    /// One record per beat of the tracker grid, i.e. whenever `beat_fract` wrapped around.
+    /// The time is interpolated back to the actual grid position.
+    pub grid_beats: Vec<Record>,
+    /// One record per simulated tick.
+    pub ticks: Vec<Record>,
+}
+
+impl Report {
+    fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "kind,sample_index,time,bpm,period,beat_fract,phase,phase_error,bpm_confidence,\
+             beats_per_bar,beat_in_bar,bar_index,downbeat_confidence,phrase_8_bar,\
+             section,build_up_progress,no_signal,subdivision,swing,subdivision_fract,\
+             tempo_candidates,beat_hypotheses"
+        )?;
+
+        let beats = self.beats.iter().map(|record| ("beat", record));
+        let grid_beats = self.grid_beats.iter().map(|record| ("grid", record));
+        let ticks = self.ticks.iter().map(|record| ("tick", record));
+        for (kind, record) in beats.chain(grid_beats).chain(ticks) {
+            // Space separated `bpm:score` pairs.
+            let tempo_candidates = record
+                .tempo_candidates
+                .iter()
+                .map(|candidate| format!("{:.1}:{:.3}", candidate.bpm, candidate.score))
+                .collect::<Vec<_>>()
+                .join(" ");
+            // Space separated `bpm@phase:score` triples.
+            let beat_hypotheses = record
+                .beat_hypotheses
+                .iter()
+                .map(|hypothesis| {
+                    format!(
+                        "{:.1}@{:.3}:{:.3}",
+                        hypothesis.bpm, hypothesis.phase, hypothesis.score
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(
+                writer,
+                "{kind},{},{},{},{},{},{},{},{},\
+                 {},{},{},{},{},\
+                 {:?},{},{},{:?},{},{},\
+                 {tempo_candidates},{beat_hypotheses}",
+                record.sample_index,
+                record.time,
+                record.bpm,
+                record.period,
+                record.beat_fract,
+                record.phase,
+                record.phase_error,
+                record.bpm_confidence,
+                record.beats_per_bar,
+                record.beat_in_bar,
+                record.bar_index,
+                record.downbeat_confidence,
+                record.phrase_8_bar,
+                record.section,
+                record.build_up_progress,
+                record.no_signal,
+                record.subdivision,
+                record.swing,
+                record.subdivision_fract,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, format: ReportFormat, writer: &mut impl Write) -> VResult<()> {
+        match format {
+            ReportFormat::Json => serde_json::to_writer_pretty(&mut *writer, self)?,
+            ReportFormat::Csv => self.write_csv(writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Rate of the simulated mainloop. Samples are fed in chunks, just like in the live app.
+const TICKS_PER_S: f32 = 60.0;
+
+/// Run the analysis over `file`, just like the live app does with the default input device, but
+/// driven by a clock which advances with the consumed samples.
+pub fn run(args: &Args, input: String, file: &AudioFile) -> Report {
+    let sample_rate = file.sample_rate as f32;
+    let clock = ManualClock::default();
+    let mut analysis = Analysis::with_clock(args, sample_rate, None, Box::new(clock.clone()));
+
+    let hop = (sample_rate / TICKS_PER_S) as usize;
+    let frames = file
+        .samples
+        .chunks_exact(2)
+        .map(|frame| [frame[0], frame[1]])
+        .collect::<Vec<_>>();
+
+    let mut beats = Vec::new();
+    let mut grid_beats = Vec::new();
+    let mut ticks = Vec::new();
+
+    for chunk in frames.chunks(hop) {
+        clock.advance(Duration::from_secs_f32(chunk.len() as f32 / sample_rate));
+        analysis.begin_tick();
+        for &frame in chunk {
+            let real_beats = analysis.real_beats;
+            analysis.on_pcm_sample(frame);
+            if analysis.real_beats != real_beats {
+                let beat_fract = analysis
+                    .bpm_tracker
+                    .sample_to_beat_fract(analysis.sample_index);
+                beats.push(Record::new(&analysis, beat_fract));
+            }
+        }
+        let fake_beats = analysis.fake_beats;
+        analysis.end_tick();
+        let record = Record::new(&analysis, analysis.beat_fract);
+
+        if analysis.fake_beats != fake_beats {
+            let offset_s = analysis.beat_fract * record.period;
+            let offset_samples = (offset_s * sample_rate) as u64;
+            grid_beats.push(Record {
+                sample_index: record.sample_index.saturating_sub(offset_samples),
+                time: record.time - offset_s as f64,
+                beat_fract: 0.0,
+                ..Record::new(&analysis, 0.0)
+            });
+        }
+        ticks.push(record);
+    }
+
+    Report {
+        input,
+        sample_rate,
+        num_samples: analysis.sample_index,
+        beats,
+        grid_beats,
+        ticks,
+    }
+}
+
+#[instrument(skip(args))]
+pub fn analyze(args: &Args, analyze_args: &AnalyzeArgs) -> VResult<()> {
+    let (input, file) = match (&analyze_args.input, args.generator.generate) {
+        (Some(path), _) => (path.display().to_string(), AudioFile::decode(path)?),
+        (None, Some(signal)) => {
+            let generator = Generator::new(signal, &args.generator, 44100);
+            let file = generator.render(analyze_args.duration_s);
+            (format!("{signal:?}"), file)
+        }
+        (None, None) => {
+            let msg = "Either an input file or a generated signal is required".to_owned();
+            return Err(Error::Local(msg));
+        }
+    };
+    let report = run(args, input, &file);
+    info!("Detected {} beats", report.beats.len());
+
+    match &analyze_args.output {
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            report.write(analyze_args.format, &mut writer)
+        }
+        None => report.write(analyze_args.format, &mut io::stdout().lock()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use crate::{analysis::evaluation::TempoClass, audio::generator::Signal};
+
+    use super::*;
+
+    fn analyze_generated(args: &[&str], duration_s: f32) -> Report {
+        let args = Args::parse_from([&["visualize-rs"], args].concat());
+        let signal = args.generator.generate.unwrap();
+        let file = Generator::new(signal, &args.generator, 44100).render(duration_s);
+        run(&args, format!("{signal:?}"), &file)
+    }
+
+    #[test]
+    fn locks_to_generated_kicks() {
+        let report = analyze_generated(&["--generate", "kicks", "--generator-bpm", "128"], 20.0);
+        let last = report.ticks.last().unwrap();
+        assert!((last.bpm - 128.0).abs() < 0.5, "locked to {} BPM", last.bpm);
+
+        // The first kick starts at sample 0, so the grid must line up with multiples of the
+        // period, give or take the latency of the onset detection.
+        let offset = last.phase.min(last.period - last.phase);
+        assert!(offset < 0.07, "grid is {offset} s off the kicks");
+    }
+
+    #[test]
+    fn stays_locked_to_a_fractional_tempo_for_minutes() {
+        let report = analyze_generated(&["--generate", "kicks", "--generator-bpm", "127.5"], 240.0);
+        for record in report.ticks.iter().filter(|record| record.time >= 20.0) {
+            assert!(
+                (record.bpm - 127.5).abs() < 0.1,
+                "{} BPM at {:.1} s",
+                record.bpm,
+                record.time
+            );
+            let offset = record.phase.min(record.period - record.phase);
+            assert!(
+                offset < 0.07,
+                "grid is {offset} s off the kicks at {:.1} s",
+                record.time
+            );
+        }
+    }
+
+    #[test]
+    fn relocks_after_silence() {
+        let args = Args::parse_from(["visualize-rs"]);
+        let kicks = |bpm: &str, duration_s| {
+            let args = Args::parse_from(["visualize-rs", "--generator-bpm", bpm]);
+            Generator::new(Signal::Kicks, &args.generator, 44100).render(duration_s)
+        };
+        let (first, second) = (kicks("128", 30.0), kicks("100", 40.0));
+        let silence = vec![0.0; 2 * 10 * 44100];
+        let file = AudioFile {
+            sample_rate: 44100,
+            samples: [first.samples, silence, second.samples].concat(),
+        };
+        let report = run(&args, "kicks, silence, kicks".to_owned(), &file);
+
+        let at = |time: f64| {
+            let mut records = report.ticks.iter();
+            records.rfind(|record| record.time <= time).unwrap()
+        };
+        assert!(!at(29.0).no_signal);
+        assert!(at(39.0).no_signal);
+        assert!(!at(45.0).no_signal);
+
+        // The trackers start over once the signal returns, instead of sliding over from 128 BPM.
+        let estimated = Some(f64::from(at(50.0).bpm));
+        assert_eq!(
+            TempoClass::classify(Some(100.0), estimated),
+            TempoClass::Correct
+        );
+        let bpm = report.ticks.last().unwrap().bpm;
+        assert!((bpm - 100.0).abs() < 0.5, "relocked to {bpm} BPM");
+    }
+
+    /// The tempo class reported after `duration_s` of kicks at `bpm`, with the default BPM range.
+    fn tempo_class_of_kicks(bpm: f32, duration_s: f32) -> TempoClass {
+        let report = analyze_generated(
+            &["--generate", "kicks", "--generator-bpm", &bpm.to_string()],
+            duration_s,
+        );
+        let estimated = report.ticks.last().map(|record| f64::from(record.bpm));
+        TempoClass::classify(Some(f64::from(bpm)), estimated)
+    }
+
+    #[test]
+    fn tracks_hip_hop_tempos() {
+        assert_eq!(tempo_class_of_kicks(87.0, 30.0), TempoClass::Correct);
+        assert_eq!(tempo_class_of_kicks(90.0, 30.0), TempoClass::Correct);
+    }
+
+    #[test]
+    fn tracks_drum_and_bass_tempo() {
+        assert_eq!(tempo_class_of_kicks(174.0, 30.0), TempoClass::Correct);
+    }
+
+    #[test]
+    fn agents_lock_to_generated_kicks() {
+        let args = ["--generate", "kicks", "--beat-tracker", "agents"];
+        let report = analyze_generated(&args, 20.0);
+        let last = report.ticks.last().unwrap();
+        assert!((last.bpm - 128.0).abs() < 0.5, "locked to {} BPM", last.bpm);
+        let offset = last.phase.min(last.period - last.phase);
+        assert!(offset < 0.07, "grid is {offset} s off the kicks");
+
+        let hypotheses = &last.beat_hypotheses;
+        assert!(hypotheses.len() > 1, "no competing hypotheses");
+        assert!(
+            hypotheses
+                .iter()
+                .any(|hypothesis| (hypothesis.bpm - 128.0).abs() < 0.5),
+            "128 BPM is not among {hypotheses:?}"
+        );
+    }
+
+    #[test]
+    fn follows_a_tempo_ramp() {
+        let args = [
+            "--generate",
+            "kicks",
+            "--generator-bpm",
+            "120",
+            "--generator-bpm-end",
+            "140",
+            "--generator-ramp-s",
+            "60",
+        ];
+        let report = analyze_generated(&args, 75.0);
+        for record in report.ticks.iter().filter(|record| record.time >= 10.0) {
+            let expected = 120.0 + 20.0 * (record.time as f32 / 60.0).min(1.0);
+            let error = (record.bpm - expected).abs();
+            assert!(
+                error < 5.0,
+                "{} BPM at {:.1} s, expected {expected}",
+                record.bpm,
+                record.time
+            );
+        }
+        let last = report.ticks.last().unwrap();
+        assert!((last.bpm - 140.0).abs() < 0.5, "ended at {} BPM", last.bpm);
+    }
+}
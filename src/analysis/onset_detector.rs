@@ -0,0 +1,37 @@
+use crate::Args;
+
+use super::{beat_detector::BeatDetector, spectral_flux::SpectralFlux};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OnsetDetectorKind {
+    /// Short-term energy of a bass band-pass
+    BassEnergy,
+    /// Rectified magnitude differences across DFT frames
+    SpectralFlux,
+    /// Bass energy of the percussive part of the spectrum, sustained bass lines are separated out
+    /// with median filters
+    PercussiveBass,
+}
+
+/// Turns the normalized PCM signal into onsets which are fed to the BPM tracker.
+pub trait OnsetDetector {
+    /// Returns the current value of the detection function and whether `x` is an onset.
+    fn on_pcm_sample(&mut self, sample_index: u64, x: f32) -> (f32, bool);
+
+    /// Current value of the detection function.
+    fn energy(&self) -> f32;
+
+    /// Sum of all values of the detection function so far.
+    fn cumulative_energy(&self) -> f32;
+
+    /// The normalized detection function and its short- and long-term thresholds.
+    fn debug_values(&self) -> [f32; 3];
+}
+
+pub fn new_onset_detector(args: &Args, sample_rate: f32) -> Box<dyn OnsetDetector> {
+    match args.onset_detector {
+        OnsetDetectorKind::BassEnergy => Box::new(BeatDetector::new(args, sample_rate)),
+        OnsetDetectorKind::SpectralFlux => Box::new(SpectralFlux::new(args, sample_rate, false)),
+        OnsetDetectorKind::PercussiveBass => Box::new(SpectralFlux::new(args, sample_rate, true)),
+    }
+}
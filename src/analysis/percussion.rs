@@ -0,0 +1,56 @@
+use crate::Args;
+
+use super::{
+    beat_detector::{Band, BeatDetector},
+    onset_detector::OnsetDetector,
+};
+
+/// Independent onset detectors on the low, mid and high bands, roughly corresponding to kick,
+/// snare and hi-hat.
+pub struct Percussion {
+    pub kick: BeatDetector,
+    pub snare: BeatDetector,
+    pub hihat: BeatDetector,
+
+    pub kick_in_tick: bool,
+    pub snare_in_tick: bool,
+    pub hihat_in_tick: bool,
+}
+
+impl Percussion {
+    pub fn new(args: &Args, sample_rate: f32) -> Self {
+        Self {
+            kick: BeatDetector::with_band(args, sample_rate, &Band::KICK),
+            snare: BeatDetector::with_band(args, sample_rate, &Band::SNARE),
+            hihat: BeatDetector::with_band(args, sample_rate, &Band::HIHAT),
+            kick_in_tick: false,
+            snare_in_tick: false,
+            hihat_in_tick: false,
+        }
+    }
+
+    pub fn begin_tick(&mut self) {
+        self.kick_in_tick = false;
+        self.snare_in_tick = false;
+        self.hihat_in_tick = false;
+    }
+
+    pub fn on_pcm_sample(&mut self, sample_index: u64, x: f32) {
+        self.kick_in_tick |= self.kick.on_pcm_sample(sample_index, x).1;
+        self.snare_in_tick |= self.snare.on_pcm_sample(sample_index, x).1;
+        self.hihat_in_tick |= self.hihat.on_pcm_sample(sample_index, x).1;
+    }
+
+    /// Normalized energies of the kick, snare and hi-hat bands.
+    pub fn energies(&self) -> [f32; 3] {
+        [
+            self.kick.stats.energy,
+            self.snare.stats.energy,
+            self.hihat.stats.energy,
+        ]
+    }
+
+    pub fn onsets(&self) -> [bool; 3] {
+        [self.kick_in_tick, self.snare_in_tick, self.hihat_in_tick]
+    }
+}
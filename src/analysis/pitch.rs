@@ -0,0 +1,125 @@
+use crate::{
+    filters::{filter::Filter, high_pass::HighPass},
+    ring_buffer::RingBuffer,
+};
+
+/// Monophonic pitch detection with YIN, meant to follow a vocal or lead line.
+///
+/// The difference of the latest samples to themselves shifted by a lag is small at the period of
+/// the fundamental. Normalized by its running mean it starts at 1, the first lag dipping below
+/// `THRESHOLD` is taken, which avoids locking onto multiples of the period. How far it dips is the
+/// confidence that the signal is voiced at all.
+pub struct PitchTracker {
+    sample_rate: f32,
+    high_pass: HighPass,
+    samples: RingBuffer<f32>,
+    window: Vec<f32>,
+    integration: usize,
+    min_lag: usize,
+    max_lag: usize,
+    difference: Vec<f32>,
+
+    /// Fundamental frequency in Hz, 0 if unvoiced.
+    pub fq: f32,
+    /// MIDI note of the fundamental with cents as the fraction, 0 if unvoiced.
+    pub note: f32,
+    /// How periodic the signal is, from 0 (noise) to 1 (pure tone).
+    pub confidence: f32,
+}
+
+impl PitchTracker {
+    /// Removes the kick and most of the bass line, which would dominate the lead otherwise.
+    const HIGH_PASS_FQ: usize = 100;
+    const MIN_FQ: f32 = 70.0;
+    const MAX_FQ: f32 = 1500.0;
+    /// Length of the compared sections of the signal.
+    const INTEGRATION_S: f32 = 0.025;
+    const THRESHOLD: f32 = 0.15;
+
+    pub fn new(sample_rate: f32) -> Self {
+        let integration = (Self::INTEGRATION_S * sample_rate) as usize;
+        let min_lag = (sample_rate / Self::MAX_FQ).floor() as usize;
+        let max_lag = (sample_rate / Self::MIN_FQ).ceil() as usize;
+        let window_size = integration + max_lag + 1;
+        Self {
+            sample_rate,
+            high_pass: HighPass::new(sample_rate as usize, Self::HIGH_PASS_FQ),
+            samples: RingBuffer::new(window_size),
+            window: vec![0.0; window_size],
+            integration,
+            min_lag,
+            max_lag,
+            difference: vec![0.0; max_lag + 2],
+
+            fq: 0.0,
+            note: 0.0,
+            confidence: 0.0,
+        }
+    }
+
+    pub fn on_pcm_sample(&mut self, x: f32) {
+        self.samples.push(self.high_pass.sample(x));
+    }
+
+    /// Cumulative mean normalized difference of the latest window, lag 0 to `max_lag + 1`.
+    fn normalized_difference(&mut self) {
+        self.samples
+            .write_to_buffer(self.window.len(), &mut self.window);
+        let window = &self.window;
+
+        let mut sum = 0.0;
+        self.difference[0] = 1.0;
+        for lag in 1..self.difference.len() {
+            let difference = window[..self.integration]
+                .iter()
+                .zip(&window[lag..lag + self.integration])
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>();
+            sum += difference;
+            self.difference[lag] = if sum > f32::EPSILON {
+                difference * lag as f32 / sum
+            } else {
+                1.0
+            };
+        }
+    }
+
+    /// Estimate the pitch of the latest samples.
+    pub fn on_tick(&mut self) {
+        self.normalized_difference();
+        let difference = &self.difference;
+
+        // The first dip below the threshold, followed down to its minimum. The global minimum if
+        // there is none, that one is most likely unvoiced though.
+        let lags = self.min_lag..=self.max_lag;
+        let lag = match lags.clone().find(|&lag| difference[lag] < Self::THRESHOLD) {
+            Some(mut lag) => {
+                while lag < self.max_lag && difference[lag + 1] < difference[lag] {
+                    lag += 1;
+                }
+                lag
+            }
+            None => lags
+                .min_by(|&a, &b| difference[a].partial_cmp(&difference[b]).unwrap())
+                .unwrap(),
+        };
+
+        // Parabolic interpolation between the lags.
+        let [left, center, right] = [difference[lag - 1], difference[lag], difference[lag + 1]];
+        let curvature = left - 2.0 * center + right;
+        let offset = if curvature > 0.0 {
+            (0.5 * (left - right) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+
+        self.confidence = (1.0 - center).clamp(0.0, 1.0);
+        if center < Self::THRESHOLD {
+            self.fq = self.sample_rate / (lag as f32 + offset);
+            self.note = 69.0 + 12.0 * (self.fq / 440.0).log2();
+        } else {
+            self.fq = 0.0;
+            self.note = 0.0;
+        }
+    }
+}
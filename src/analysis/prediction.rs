@@ -0,0 +1,45 @@
+use super::beat_tracker::BeatTracker;
+
+/// The beat grid at the time the current frame will be on screen, rather than at the last consumed
+/// sample. Swapchain queueing and the compositor delay every frame, shaders animating on the
+/// predicted beat are in time with what is heard.
+#[derive(Default)]
+pub struct PredictedBeat {
+    pub beat_fract: f32,
+    /// Counts the beats of the grid like `Analysis::fake_beats`, up to one beat earlier.
+    pub beat_index: u32,
+    /// Whether a beat of the grid falls into the current frame.
+    pub is_beat: bool,
+    /// Seconds from the presentation of the current frame until the next beat.
+    pub next_beat_s: f32,
+}
+
+impl PredictedBeat {
+    /// Evaluate the grid of `tracker` `latency_s` after `sample_index`.
+    pub fn update(
+        &mut self,
+        tracker: &dyn BeatTracker,
+        sample_index: u64,
+        sample_rate: f32,
+        latency_s: f32,
+        fake_beats: u32,
+    ) {
+        let presented_sample = sample_index + (latency_s * sample_rate) as u64;
+        let fract_pre = self.beat_fract;
+        self.beat_fract = tracker.sample_to_beat_fract(presented_sample);
+
+        // Same criterion as the beats of the grid, so that both count the same beats.
+        self.is_beat = self.beat_fract < 0.1 && fract_pre > 0.9;
+        if self.is_beat {
+            self.beat_index += 1;
+        }
+        self.beat_index = self.beat_index.clamp(fake_beats, fake_beats + 1);
+
+        self.next_beat_s = (1.0 - self.beat_fract) * tracker.bpm().period;
+    }
+
+    /// Beats the presented grid is ahead of the analyzed one, 0 or 1.
+    pub fn beats_ahead(&self, fake_beats: u32) -> u32 {
+        self.beat_index - fake_beats
+    }
+}
@@ -0,0 +1,221 @@
+use serde::Serialize;
+use tracing::debug;
+
+use crate::filters::{alpha_avg::AlphaAvg, biquad_band_pass::BiquadBandPass, filter::Filter};
+
+use super::beat_detector::Band;
+
+/// Coarse arrangement state of the music, as far as it matters for a show.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub enum Section {
+    /// The kick is playing, nothing special is going on.
+    #[default]
+    Steady,
+    /// The kick dropped out.
+    Breakdown,
+    /// Still without kick, but energy or brightness are rising towards a drop.
+    BuildUp,
+    /// The kick returned after a breakdown or build-up.
+    Drop,
+}
+
+/// Classifies the arrangement from long-term trends of the loudness, of the spectral brightness
+/// and of the presence of the kick.
+///
+/// The levels of a track are learned while the kick is playing. A breakdown starts once most of
+/// the energy of the kick band is gone, a build-up once energy or brightness rise during a
+/// breakdown, and the drop is triggered as soon as the kick band is back.
+pub struct SectionTracker {
+    kick_band: BiquadBandPass,
+    hop_energy: f32,
+    hop_kick_energy: f32,
+    brightness: f32,
+
+    energy_fast: AlphaAvg,
+    energy_slow: AlphaAvg,
+    brightness_fast: AlphaAvg,
+    brightness_slow: AlphaAvg,
+    /// Energy of the kick band, smoothed for breakdown detection. The drop is triggered on the
+    /// unsmoothed energy, to be in time.
+    kick_energy: AlphaAvg,
+    /// Energy of the kick band while the kick is playing.
+    kick_energy_reference: AlphaAvg,
+
+    hops_per_s: f32,
+    hops_kick_gone: u32,
+    hops_rising: u32,
+    hops_not_rising: u32,
+    hops_in_section: u32,
+    build_up_energy: f32,
+    build_up_brightness: f32,
+
+    pub section: Section,
+    /// How far the current build-up has risen, in `[0, 1]`.
+    pub build_up_progress: f32,
+    /// Whether a drop started during the current tick.
+    pub drop_in_tick: bool,
+}
+
+impl SectionTracker {
+    const HOP_SIZE: u64 = 512;
+
+    const FAST_S: f32 = 1.0;
+    const SLOW_S: f32 = 8.0;
+    const KICK_ENERGY_S: f32 = 0.5;
+    const KICK_REFERENCE_S: f32 = 20.0;
+
+    /// Kick presence, relative to its reference, below which the kick counts as gone.
+    const KICK_GONE: f32 = 0.25;
+    /// Kick presence, relative to its reference, above which the kick counts as back.
+    const KICK_BACK: f32 = 0.3;
+    /// How long the kick needs to be gone for a breakdown.
+    const BREAKDOWN_S: f32 = 1.0;
+    /// Rise of the fast over the slow average which starts a build-up.
+    const RISE_DB: f32 = 2.0;
+    const RISE_OCTAVES: f32 = 0.25;
+    /// How long the rise needs to last for a build-up.
+    const RISING_S: f32 = 1.0;
+    /// Rise since the start of the build-up which counts as fully built up.
+    const FULL_RISE_DB: f32 = 9.0;
+    const FULL_RISE_OCTAVES: f32 = 3.0;
+    /// A build-up falls back to a breakdown when it stops rising for this long.
+    const STALL_S: f32 = 4.0;
+    /// Duration of the drop section before it is considered steady.
+    const DROP_S: f32 = 8.0;
+
+    pub fn new(sample_rate: f32) -> Self {
+        let hops_per_s = sample_rate / Self::HOP_SIZE as f32;
+        let avg =
+            |time_constant_s: f32| AlphaAvg::new((-1.0 / (time_constant_s * hops_per_s)).exp());
+
+        Self {
+            kick_band: BiquadBandPass::new(sample_rate, Band::KICK.center_fq, Band::KICK.q),
+            hop_energy: 0.0,
+            hop_kick_energy: 0.0,
+            brightness: 0.0,
+
+            energy_fast: avg(Self::FAST_S),
+            energy_slow: avg(Self::SLOW_S),
+            brightness_fast: avg(Self::FAST_S),
+            brightness_slow: avg(Self::SLOW_S),
+            kick_energy: avg(Self::KICK_ENERGY_S),
+            kick_energy_reference: avg(Self::KICK_REFERENCE_S),
+
+            hops_per_s,
+            hops_kick_gone: 0,
+            hops_rising: 0,
+            hops_not_rising: 0,
+            hops_in_section: 0,
+            build_up_energy: 0.0,
+            build_up_brightness: 0.0,
+
+            section: Section::Steady,
+            build_up_progress: 0.0,
+            drop_in_tick: false,
+        }
+    }
+
+    pub fn begin_tick(&mut self) {
+        self.drop_in_tick = false;
+    }
+
+    /// Feed the input before normalization. Otherwise a quiet breakdown would be amplified and
+    /// other sounds replacing the kick would suppress the kick band.
+    pub fn on_pcm_sample(&mut self, sample_index: u64, raw: f32) {
+        self.hop_energy += raw * raw;
+        self.hop_kick_energy += self.kick_band.sample(raw).powi(2);
+
+        if sample_index % Self::HOP_SIZE == 0 {
+            self.on_hop();
+        }
+    }
+
+    /// Update the brightness from the spectral centroid of the latest DFT in Hz.
+    pub fn on_centroid(&mut self, centroid: f32) {
+        // In octaves above 1 Hz.
+        self.brightness = centroid.max(1.0).log2();
+    }
+
+    fn on_hop(&mut self) {
+        let hop = Self::HOP_SIZE as f32;
+        let energy_db = 10.0 * (self.hop_energy / hop + 1e-10).log10();
+        let kick_energy = self.hop_kick_energy / hop;
+        self.hop_energy = 0.0;
+        self.hop_kick_energy = 0.0;
+
+        let energy_fast = self.energy_fast.sample(energy_db);
+        let energy_slow = self.energy_slow.sample(energy_db);
+        let brightness_fast = self.brightness_fast.sample(self.brightness);
+        let brightness_slow = self.brightness_slow.sample(self.brightness);
+        let kick_energy_instant = kick_energy;
+        let kick_energy = self.kick_energy.sample(kick_energy);
+
+        // The reference is only learned while the kick is supposed to play.
+        if matches!(self.section, Section::Steady | Section::Drop) {
+            self.kick_energy_reference.sample(kick_energy);
+        }
+        let reference = self.kick_energy_reference.avg.max(f32::EPSILON);
+        let kick_presence = kick_energy / reference;
+        let kick_is_back = kick_energy_instant / reference > Self::KICK_BACK;
+
+        if kick_presence < Self::KICK_GONE && !kick_is_back {
+            self.hops_kick_gone += 1;
+        } else {
+            self.hops_kick_gone = 0;
+        }
+        let kick_is_gone = self.hops_kick_gone as f32 > Self::BREAKDOWN_S * self.hops_per_s;
+
+        let is_rising = energy_fast - energy_slow > Self::RISE_DB
+            || brightness_fast - brightness_slow > Self::RISE_OCTAVES;
+        if is_rising {
+            self.hops_rising += 1;
+            self.hops_not_rising = 0;
+        } else {
+            self.hops_rising = 0;
+            self.hops_not_rising += 1;
+        }
+        let keeps_rising = self.hops_rising as f32 > Self::RISING_S * self.hops_per_s;
+        let stalled = self.hops_not_rising as f32 > Self::STALL_S * self.hops_per_s;
+
+        self.hops_in_section += 1;
+        let section_s = self.hops_in_section as f32 / self.hops_per_s;
+
+        let next = match self.section {
+            Section::Steady | Section::Drop if kick_is_gone => Section::Breakdown,
+            Section::Drop if section_s > Self::DROP_S => Section::Steady,
+            Section::Breakdown | Section::BuildUp if kick_is_back => Section::Drop,
+            Section::Breakdown if keeps_rising => Section::BuildUp,
+            Section::BuildUp if stalled => Section::Breakdown,
+            section => section,
+        };
+
+        let enters_breakdown = matches!(self.section, Section::Steady | Section::Drop);
+        if next == Section::Breakdown && enters_breakdown {
+            // Trends within the breakdown are measured from its own level.
+            self.energy_slow.avg = energy_fast;
+            self.brightness_slow.avg = brightness_fast;
+        }
+
+        if next == Section::BuildUp {
+            if self.section != Section::BuildUp {
+                self.build_up_energy = energy_fast;
+                self.build_up_brightness = brightness_fast;
+                self.build_up_progress = 0.0;
+            }
+            let energy_rise = (energy_fast - self.build_up_energy) / Self::FULL_RISE_DB;
+            let brightness_rise =
+                (brightness_fast - self.build_up_brightness) / Self::FULL_RISE_OCTAVES;
+            let progress = 0.5 * (energy_rise.clamp(0.0, 1.0) + brightness_rise.clamp(0.0, 1.0));
+            self.build_up_progress = self.build_up_progress.max(progress);
+        } else {
+            self.build_up_progress = 0.0;
+        }
+
+        if next != self.section {
+            debug!("Section {:?} -> {next:?}", self.section);
+            self.drop_in_tick |= next == Section::Drop;
+            self.section = next;
+            self.hops_in_section = 0;
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{
+    filters::{filter::Filter, max_decay_normalizer::MaxDecayNormalizer},
+    ring_buffer::RingBuffer,
+    Args,
+};
+
+use super::{dft::Dft, hpss::Hpss, onset_detector::OnsetDetector};
+
+/// Onset detection on the half-wave rectified increase of the (log-compressed) spectrum between
+/// consecutive DFT frames. An onset is registered when the flux rises over an adaptive threshold
+/// derived from the median of the recent flux values.
+///
+/// With harmonic-percussive separation, the detection function is the bass energy of the
+/// percussive part of the spectrum instead, so that sustained bass lines don't mask the kick.
+pub struct SpectralFlux {
+    samples: RingBuffer<f32>,
+    dft: Dft,
+    hpss: Option<Hpss>,
+    bass_bins: usize,
+    previous_magnitudes: Vec<f32>,
+
+    normalizer: MaxDecayNormalizer,
+    flux: f32,
+    cumulative: f32,
+    history: RingBuffer<f32>,
+    sorted_history: Vec<f32>,
+    median: f32,
+    threshold: f32,
+
+    over_threshold: bool,
+    frames_since_last_onset: u32,
+    min_frames_threshold: u32,
+}
+
+impl SpectralFlux {
+    const DFT_SIZE: usize = 1024;
+    const HOP_SIZE: u64 = 256;
+    /// Compression of the magnitudes, `log(1 + GAMMA * x)`.
+    const GAMMA: f32 = 10.0;
+    const MEDIAN_WINDOW_S: f32 = 0.5;
+    const THRESHOLD_SCALE: f32 = 1.5;
+    const THRESHOLD_OFFSET: f32 = 0.1;
+    /// Upper end of the bass range of the percussive part.
+    const BASS_FQ: f32 = 180.0;
+
+    pub fn new(args: &Args, sample_rate: f32, percussive: bool) -> Self {
+        let frames_per_s = sample_rate / Self::HOP_SIZE as f32;
+        let history_size = (frames_per_s * Self::MEDIAN_WINDOW_S) as usize;
+        let min_frames_threshold = frames_per_s * 60.0 / args.fastest_bpm as f32;
+
+        let num_fqs = Self::DFT_SIZE / 2 + 1;
+        let bin_fq = sample_rate / Self::DFT_SIZE as f32;
+        let hpss = percussive.then(|| Hpss::with_defaults(num_fqs, sample_rate, bin_fq));
+
+        Self {
+            samples: RingBuffer::new(Self::DFT_SIZE),
+            dft: Dft::new(Self::DFT_SIZE, sample_rate),
+            hpss,
+            bass_bins: (Self::BASS_FQ / bin_fq).round() as usize,
+            previous_magnitudes: vec![0.0; num_fqs],
+
+            normalizer: MaxDecayNormalizer::new(0.999, 0.1),
+            flux: 0.0,
+            cumulative: 0.0,
+            history: RingBuffer::new(history_size),
+            sorted_history: Vec::with_capacity(history_size),
+            median: 0.0,
+            threshold: 0.0,
+
+            over_threshold: false,
+            frames_since_last_onset: 0,
+            min_frames_threshold: min_frames_threshold.round() as u32,
+        }
+    }
+
+    /// Rectified increase of the compressed `magnitudes` over `previous`, which are updated.
+    fn flux(magnitudes: impl Iterator<Item = f32>, previous: &mut [f32]) -> f32 {
+        let mut flux = 0.0;
+        for (magnitude, previous) in magnitudes.zip(previous.iter_mut()) {
+            let magnitude = (1.0 + Self::GAMMA * magnitude).ln();
+            flux += (magnitude - *previous).max(0.0);
+            *previous = magnitude;
+        }
+        flux
+    }
+
+    fn on_frame(&mut self) -> bool {
+        self.samples
+            .write_to_buffer(Self::DFT_SIZE, self.dft.get_input_vec());
+        self.dft.run_transform();
+
+        let flux = match &mut self.hpss {
+            Some(hpss) => {
+                hpss.separate(self.dft.magnitudes(), Self::HOP_SIZE);
+                // Skip the DC bin.
+                let bass = hpss.percussive.iter().skip(1).take(self.bass_bins);
+                bass.map(|x| x * x).sum()
+            }
+            None => Self::flux(self.dft.magnitudes(), &mut self.previous_magnitudes),
+        };
+        self.flux = self.normalizer.sample(flux);
+
+        self.history.push(self.flux);
+        self.sorted_history.clear();
+        self.sorted_history.extend_from_slice(&self.history.data);
+        self.sorted_history
+            .sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        self.median = self.sorted_history[self.sorted_history.len() / 2];
+        self.threshold = Self::THRESHOLD_SCALE * self.median + Self::THRESHOLD_OFFSET;
+
+        let was_over_threshold = self.over_threshold;
+        self.over_threshold = self.flux > self.threshold;
+
+        let sub_bpm_limit = self.frames_since_last_onset > self.min_frames_threshold;
+        let is_onset = !was_over_threshold && self.over_threshold && sub_bpm_limit;
+        if is_onset {
+            self.frames_since_last_onset = 0;
+        }
+        self.frames_since_last_onset += 1;
+        is_onset
+    }
+}
+
+impl OnsetDetector for SpectralFlux {
+    fn on_pcm_sample(&mut self, sample_index: u64, x: f32) -> (f32, bool) {
+        self.samples.push(x);
+
+        let is_onset = sample_index % Self::HOP_SIZE == 0 && self.on_frame();
+        self.cumulative += self.flux;
+        (self.flux, is_onset)
+    }
+
+    fn energy(&self) -> f32 {
+        self.flux
+    }
+
+    fn cumulative_energy(&self) -> f32 {
+        self.cumulative
+    }
+
+    fn debug_values(&self) -> [f32; 3] {
+        [self.flux, self.threshold, self.median]
+    }
+}
@@ -0,0 +1,237 @@
+use serde::Serialize;
+
+use crate::{
+    filters::{alpha_avg::AlphaAvg, filter::Filter},
+    ring_buffer::RingBuffer,
+    Args,
+};
+
+use super::dft::Dft;
+
+/// A tempo hypothesis, e.g. the strongest periodicity or one of its octaves.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct TempoCandidate {
+    pub bpm: f32,
+    /// Normalized autocorrelation at the candidate's lag, in `[0, 1]`.
+    pub support: f32,
+    /// Support weighted with the tempo prior.
+    pub score: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct TempoEstimate {
+    pub bpm: f32,
+    /// Support of the chosen candidate.
+    pub confidence: f32,
+    /// All considered hypotheses, best first.
+    pub candidates: Vec<TempoCandidate>,
+    /// Sample index of the most recent beat of the chosen tempo.
+    pub beat_sample: u64,
+}
+
+/// Tempo estimation by autocorrelating the onset-strength envelope over the last few seconds.
+/// Unlike the inter-onset deltas, the periodicity survives individual missed or spurious onsets.
+pub struct Tempogram {
+    frames_per_s: f32,
+    slowest_bpm: f32,
+    fastest_bpm: f32,
+    hop_sum: f32,
+    previous: f32,
+    /// Widens the peaks of the autocorrelation so that lags between two frames are not missed.
+    smoothing: AlphaAvg,
+
+    envelope: RingBuffer<f32>,
+    acf: Dft,
+    min_lag: usize,
+    max_lag: usize,
+    frames_since_estimate: usize,
+    frames_per_estimate: usize,
+
+    pub estimate: Option<TempoEstimate>,
+}
+
+impl Tempogram {
+    const HOP_SIZE: u64 = 256;
+    const WINDOW_S: f32 = 6.0;
+    const UPDATE_S: f32 = 0.5;
+    /// Tempo multiples of the strongest periodicity which are considered as well.
+    const OCTAVE_FACTORS: [f32; 5] = [1.0 / 3.0, 0.5, 1.0, 2.0, 3.0];
+    const PRIOR_BPM: f32 = 120.0;
+    const PRIOR_OCTAVES: f32 = 1.0;
+    /// Relative lag deviation when searching for the peak of an octave.
+    const PEAK_RADIUS: f32 = 0.03;
+    /// Weight of the periodicity at half the candidate's lag. If the off-beats are as periodic
+    /// as the beats themselves, the candidate is most likely half the actual tempo.
+    const OFFBEAT_PENALTY: f32 = 0.5;
+
+    pub fn new(args: &Args, sample_rate: f32) -> Self {
+        let frames_per_s = sample_rate / Self::HOP_SIZE as f32;
+        let window = (frames_per_s * Self::WINDOW_S) as usize;
+        let lag_of_bpm = |bpm: u32| frames_per_s * 60.0 / bpm as f32;
+
+        Self {
+            frames_per_s,
+            slowest_bpm: args.slowest_bpm as f32,
+            fastest_bpm: args.fastest_bpm as f32,
+            hop_sum: 0.0,
+            previous: 0.0,
+            smoothing: AlphaAvg::new(0.6),
+
+            envelope: RingBuffer::new(window),
+            // Zero-padded to at least twice the window, see `Dft::autocorrelate`.
+            acf: Dft::new((2 * window).next_power_of_two(), sample_rate),
+            min_lag: (lag_of_bpm(args.fastest_bpm).floor() as usize).max(1),
+            max_lag: lag_of_bpm(args.slowest_bpm).ceil() as usize,
+            frames_since_estimate: 0,
+            frames_per_estimate: (frames_per_s * Self::UPDATE_S) as usize,
+
+            estimate: None,
+        }
+    }
+
+    /// Feed the onset detection function. Returns a new estimate every `UPDATE_S` seconds, given
+    /// that the envelope is not silent.
+    pub fn on_pcm_sample(
+        &mut self,
+        sample_index: u64,
+        onset_strength: f32,
+    ) -> Option<TempoEstimate> {
+        self.hop_sum += onset_strength;
+        if sample_index % Self::HOP_SIZE != 0 {
+            return None;
+        }
+
+        // Half-wave rectified increase of the hop average.
+        let value = self.hop_sum / Self::HOP_SIZE as f32;
+        self.hop_sum = 0.0;
+        let novelty = (value - self.previous).max(0.0);
+        self.envelope.push(self.smoothing.sample(novelty));
+        self.previous = value;
+
+        self.frames_since_estimate += 1;
+        if self.frames_since_estimate < self.frames_per_estimate {
+            return None;
+        }
+        self.frames_since_estimate = 0;
+
+        self.estimate = self.estimate_tempo(sample_index);
+        self.estimate.clone()
+    }
+
+    fn estimate_tempo(&mut self, sample_index: u64) -> Option<TempoEstimate> {
+        let window = self.envelope.size;
+        let input = self.acf.get_input_vec();
+        self.envelope.write_to_buffer(window, &mut input[..window]);
+        input[window..].fill(0.0);
+
+        let mean = input[..window].iter().sum::<f32>() / window as f32;
+        for x in input[..window].iter_mut() {
+            *x -= mean;
+        }
+
+        self.acf.autocorrelate();
+        let acf = &self.acf.input;
+        if acf[0] <= f32::EPSILON {
+            return None;
+        }
+
+        // The strongest periodicity is often an octave off the perceived tempo. Consider its
+        // multiples as well and let the prior decide.
+        let (peak, _) = acf[self.min_lag..=self.max_lag]
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap();
+        let (peak_lag, _) = Self::refine_peak(acf, peak + self.min_lag);
+        let peak_bpm = self.lag_to_bpm(peak_lag);
+
+        let mut candidates = Self::OCTAVE_FACTORS
+            .iter()
+            .map(|factor| peak_bpm * factor)
+            .filter(|bpm| self.slowest_bpm <= *bpm && *bpm <= self.fastest_bpm)
+            .map(|bpm| {
+                let (lag, value) = self.peak_near(self.bpm_to_lag(bpm));
+                let (_, offbeat_value) = self.peak_near(0.5 * lag);
+                let bpm = self.lag_to_bpm(lag);
+                let support = (value / acf[0]).clamp(0.0, 1.0);
+                let offbeat_support = (offbeat_value / acf[0]).clamp(0.0, 1.0);
+                let periodicity = (support - Self::OFFBEAT_PENALTY * offbeat_support).max(0.0);
+                TempoCandidate {
+                    bpm,
+                    support,
+                    score: periodicity * Self::prior(bpm),
+                }
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_unstable_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+
+        let best = candidates.first()?;
+        let beat_offset = self.beat_offset(self.bpm_to_lag(best.bpm));
+        Some(TempoEstimate {
+            bpm: best.bpm,
+            confidence: best.support,
+            beat_sample: sample_index.saturating_sub(beat_offset as u64 * Self::HOP_SIZE),
+            candidates,
+        })
+    }
+
+    /// Frames since the most recent beat of a grid with period `lag`, chosen such that the grid
+    /// collects the most onset strength.
+    fn beat_offset(&self, lag: f32) -> usize {
+        let window = self.envelope.size;
+        let newest = self.envelope.prev_index + window;
+        let num_beats = ((window - 1) as f32 / lag) as usize;
+        (0..lag as usize)
+            .map(|offset| {
+                let strength = (0..num_beats)
+                    .map(|beat| {
+                        let back = offset + (beat as f32 * lag).round() as usize;
+                        self.envelope.data[(newest - back) % window]
+                    })
+                    .sum::<f32>();
+                (offset, strength)
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map_or(0, |(offset, _)| offset)
+    }
+
+    /// Log-normal preference for tempi around `PRIOR_BPM`, 1 at its center.
+    fn prior(bpm: f32) -> f32 {
+        let octaves = (bpm / Self::PRIOR_BPM).log2() / Self::PRIOR_OCTAVES;
+        (-0.5 * octaves * octaves).exp()
+    }
+
+    fn lag_to_bpm(&self, lag: f32) -> f32 {
+        60.0 * self.frames_per_s / lag
+    }
+
+    fn bpm_to_lag(&self, bpm: f32) -> f32 {
+        60.0 * self.frames_per_s / bpm
+    }
+
+    /// Local maximum of the autocorrelation within a few percent of `lag`.
+    fn peak_near(&self, lag: f32) -> (f32, f32) {
+        let acf = &self.acf.input;
+        let radius = (Self::PEAK_RADIUS * lag).ceil();
+        let from = ((lag - radius).floor() as usize).max(1);
+        let to = ((lag + radius).ceil() as usize).min(acf.len() - 2);
+        let (peak, _) = acf[from..=to]
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .unwrap();
+        Self::refine_peak(acf, peak + from)
+    }
+
+    /// Parabolic interpolation of the peak at `index` for sub-frame lag resolution.
+    fn refine_peak(acf: &[f32], index: usize) -> (f32, f32) {
+        let (left, center, right) = (acf[index - 1], acf[index], acf[index + 1]);
+        let curvature = left - 2.0 * center + right;
+        if curvature >= 0.0 {
+            return (index as f32, center);
+        }
+        let shift = (0.5 * (left - right) / curvature).clamp(-0.5, 0.5);
+        let value = center - 0.25 * (left - right) * shift;
+        (index as f32 + shift, value)
+    }
+}
@@ -0,0 +1,163 @@
+use tracing::debug;
+
+use crate::Args;
+
+/// Reasons to forget what has been learned about the music so far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackEvent {
+    /// The input fell silent.
+    SignalLost,
+    /// The input is back after silence.
+    SignalReturned,
+    /// The timbre changed abruptly, most likely a different track started.
+    TrackChanged,
+}
+
+/// Watches the raw input for silence and the spectrum for abrupt changes.
+///
+/// Silence is detected with an energy gate on the input before normalization. Track changes are
+/// detected by comparing the average spectrum of the last two seconds with the one of the last ten
+/// seconds, leaving out the overall level which the normalizer changes anyway. Breakdowns and
+/// drops change the spectrum just as much, so a change only counts if it starts while the kick is
+/// playing and the kick keeps playing for a few seconds.
+pub struct TrackMonitor {
+    sample_rate: f32,
+    gate: f32,
+    silent_hops_until_lost: u32,
+    hop_energy: f32,
+    silent_hops: u32,
+
+    short_spectrum: Vec<f32>,
+    long_spectrum: Vec<f32>,
+    samples_since_change: u64,
+    /// Samples since the novelty rose over the threshold, while the kick kept playing.
+    samples_novel: Option<u64>,
+    was_novel: bool,
+
+    pub no_signal: bool,
+    /// Mean deviation of the short from the long spectrum in dB, with the level removed.
+    pub novelty: f32,
+}
+
+impl TrackMonitor {
+    const HOP_SIZE: u64 = 512;
+    const SHORT_S: f32 = 2.0;
+    const LONG_S: f32 = 10.0;
+    const TRACK_CHANGE_DB: f32 = 3.5;
+    /// How long the novelty has to stay over the threshold.
+    const CONFIRM_S: f32 = 3.0;
+    /// No track change is reported until the long spectrum has settled, nor right after one.
+    const MIN_TRACK_S: f32 = 20.0;
+
+    pub fn new(args: &Args, sample_rate: f32, num_bins: usize) -> Self {
+        let hops_per_s = sample_rate / Self::HOP_SIZE as f32;
+        Self {
+            sample_rate,
+            gate: 10f32.powf(args.silence_gate_db / 10.0),
+            silent_hops_until_lost: (args.silence_s * hops_per_s).ceil() as u32,
+            hop_energy: 0.0,
+            silent_hops: 0,
+
+            short_spectrum: vec![0.0; num_bins],
+            long_spectrum: vec![0.0; num_bins],
+            samples_since_change: 0,
+            samples_novel: None,
+            was_novel: false,
+
+            no_signal: false,
+            novelty: 0.0,
+        }
+    }
+
+    /// Feed the input before normalization.
+    pub fn on_pcm_sample(&mut self, sample_index: u64, raw: f32) -> Option<TrackEvent> {
+        self.hop_energy += raw * raw;
+        if sample_index % Self::HOP_SIZE != 0 {
+            return None;
+        }
+
+        let is_silent = self.hop_energy / (Self::HOP_SIZE as f32) < self.gate;
+        self.hop_energy = 0.0;
+
+        if !is_silent {
+            self.silent_hops = 0;
+            if self.no_signal {
+                debug!("Signal returned");
+                self.no_signal = false;
+                self.samples_since_change = 0;
+                return Some(TrackEvent::SignalReturned);
+            }
+            return None;
+        }
+
+        self.silent_hops += 1;
+        if !self.no_signal && self.silent_hops >= self.silent_hops_until_lost {
+            debug!("Signal lost");
+            self.no_signal = true;
+            return Some(TrackEvent::SignalLost);
+        }
+        None
+    }
+
+    /// Feed the log spectrum (in dB) once per tick, `samples` after the previous one.
+    pub fn on_spectrum(
+        &mut self,
+        spectrum: impl Iterator<Item = f32>,
+        samples: u64,
+        kick_is_playing: bool,
+    ) -> Option<TrackEvent> {
+        if self.no_signal {
+            return None;
+        }
+
+        let elapsed_s = samples as f32 / self.sample_rate;
+        let short_alpha = (-elapsed_s / Self::SHORT_S).exp();
+        let long_alpha = (-elapsed_s / Self::LONG_S).exp();
+        let first = self.samples_since_change == 0;
+        for ((short, long), value) in self
+            .short_spectrum
+            .iter_mut()
+            .zip(self.long_spectrum.iter_mut())
+            .zip(spectrum)
+        {
+            if first {
+                (*short, *long) = (value, value);
+            }
+            *short = short_alpha * *short + (1.0 - short_alpha) * value;
+            *long = long_alpha * *long + (1.0 - long_alpha) * value;
+        }
+        self.samples_since_change += samples;
+
+        let num_bins = self.short_spectrum.len() as f32;
+        let differences = self
+            .short_spectrum
+            .iter()
+            .zip(self.long_spectrum.iter())
+            .map(|(short, long)| short - long);
+        let level = differences.clone().sum::<f32>() / num_bins;
+        self.novelty = differences.map(|d| (d - level).abs()).sum::<f32>() / num_bins;
+
+        let is_novel = self.novelty > Self::TRACK_CHANGE_DB;
+        self.samples_novel = match self.samples_novel {
+            _ if !is_novel || !kick_is_playing => None,
+            Some(novel) => Some(novel + samples),
+            // The novelty has to rise over the threshold while the kick is playing, not only
+            // outlast a breakdown.
+            None if self.was_novel => None,
+            None => Some(0),
+        };
+        self.was_novel = is_novel;
+
+        let settled = self.samples_since_change as f32 > Self::MIN_TRACK_S * self.sample_rate;
+        let confirmed = self
+            .samples_novel
+            .is_some_and(|novel| novel as f32 > Self::CONFIRM_S * self.sample_rate);
+        if settled && confirmed {
+            debug!("Track changed, spectral novelty {:.1} dB", self.novelty);
+            self.samples_since_change = 0;
+            self.samples_novel = None;
+            return Some(TrackEvent::TrackChanged);
+        }
+        None
+    }
+}
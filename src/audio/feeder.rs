@@ -0,0 +1,135 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::thread_shared::ThreadShared;
+
+use super::stereo::Stereo;
+
+/// A source of interleaved stereo samples which replaces the input device.
+pub trait Source: Send + 'static {
+    /// Fill `samples` and return how many were written. Writing fewer samples than requested
+    /// means that the source is exhausted.
+    fn read(&mut self, samples: &mut [f32]) -> usize;
+}
+
+/// Writes the samples of a `Source` into the stereo ring buffer.
+///
+/// In real-time mode a thread writes small chunks paced by the wall clock, emulating a live input
+/// device. Otherwise the samples are written on demand by calling `pump` from the mainloop.
+pub struct Feeder {
+    finished: Arc<AtomicBool>,
+    feed: Feed,
+}
+
+enum Feed {
+    Realtime {
+        running: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    },
+    OnDemand {
+        source: Box<dyn Source>,
+        chunk: Vec<f32>,
+    },
+}
+
+impl Feeder {
+    /// Chunk duration when feeding in real time.
+    const CHUNK_S: f32 = 0.01;
+
+    fn run_realtime(
+        mut source: Box<dyn Source>,
+        sample_rate: u32,
+        ring_buffer: &ThreadShared<Stereo>,
+        running: &AtomicBool,
+        finished: &AtomicBool,
+    ) {
+        let chunk_frames = (sample_rate as f32 * Self::CHUNK_S) as usize;
+        let mut chunk = vec![0.0; 2 * chunk_frames];
+        let start = Instant::now();
+        let mut written_frames = 0;
+
+        while running.load(Ordering::SeqCst) {
+            let num_samples = source.read(&mut chunk);
+            ring_buffer.write().write_samples(&chunk[..num_samples]);
+            if num_samples < chunk.len() {
+                debug!("Input source finished");
+                finished.store(true, Ordering::SeqCst);
+                return;
+            }
+
+            // Pace relative to the start to prevent drift.
+            written_frames += chunk_frames;
+            let deadline = Duration::from_secs_f32(written_frames as f32 / sample_rate as f32);
+            if let Some(wait) = deadline.checked_sub(start.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+
+    pub fn new(
+        source: Box<dyn Source>,
+        sample_rate: u32,
+        ring_buffer: &ThreadShared<Stereo>,
+        realtime: bool,
+    ) -> Self {
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let feed = if realtime {
+            let running = Arc::new(AtomicBool::new(true));
+            let thread = std::thread::spawn({
+                let ring_buffer = ring_buffer.clone();
+                let running = running.clone();
+                let finished = finished.clone();
+                move || Self::run_realtime(source, sample_rate, &ring_buffer, &running, &finished)
+            });
+            Feed::Realtime {
+                running,
+                thread: Some(thread),
+            }
+        } else {
+            Feed::OnDemand {
+                source,
+                chunk: Vec::new(),
+            }
+        };
+
+        Feeder { finished, feed }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    /// Write up to `max_frames` frames into the ring buffer. Only has an effect when not feeding
+    /// in real time.
+    pub fn pump(&mut self, ring_buffer: &ThreadShared<Stereo>, max_frames: usize) {
+        if let Feed::OnDemand { source, chunk } = &mut self.feed {
+            chunk.resize(2 * max_frames, 0.0);
+            let num_samples = source.read(chunk);
+            ring_buffer.write().write_samples(&chunk[..num_samples]);
+
+            if num_samples < chunk.len() {
+                self.finished.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl Drop for Feeder {
+    fn drop(&mut self) {
+        if let Feed::Realtime { running, thread } = &mut self.feed {
+            running.store(false, Ordering::SeqCst);
+            if let Some(thread) = thread.take() {
+                thread.join().expect("Failed to join input feeder thread");
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::path::Path;
+
+use tracing::{info, instrument};
+
+use crate::error::{Error, VResult};
+
+use super::feeder::Source;
+
+/// A fully decoded audio file, converted to interleaved stereo `f32` samples.
+pub struct AudioFile {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl AudioFile {
+    pub fn num_frames(&self) -> usize {
+        self.samples.len() / 2
+    }
+
+    /// Mono files are duplicated onto both channels, additional channels are dropped.
+    fn from_interleaved(sample_rate: u32, num_channels: usize, samples: &[f32]) -> VResult<Self> {
+        if num_channels == 0 {
+            return Err(Error::Local("Audio file without channels".to_owned()));
+        }
+        let samples = samples
+            .chunks_exact(num_channels)
+            .flat_map(|frame| [frame[0], frame[num_channels.min(2) - 1]])
+            .collect();
+        Ok(AudioFile {
+            sample_rate,
+            samples,
+        })
+    }
+
+    fn decode_wav(path: &Path) -> VResult<Self> {
+        let mut reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let samples = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+        };
+        Self::from_interleaved(spec.sample_rate, spec.channels as usize, &samples)
+    }
+
+    fn decode_flac(path: &Path) -> VResult<Self> {
+        let mut reader = claxon::FlacReader::open(path)?;
+        let info = reader.streaminfo();
+        let scale = 1.0 / (1u64 << (info.bits_per_sample - 1)) as f32;
+        let samples = reader
+            .samples()
+            .map(|sample| sample.map(|sample| sample as f32 * scale))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::from_interleaved(info.sample_rate, info.channels as usize, &samples)
+    }
+
+    #[instrument(name = "AudioFile::decode")]
+    pub fn decode(path: &Path) -> VResult<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        let file = match extension.as_deref() {
+            Some("wav") => Self::decode_wav(path)?,
+            Some("flac") => Self::decode_flac(path)?,
+            _ => {
+                let msg = format!("Unsupported audio file '{}'", path.display());
+                return Err(Error::Local(msg));
+            }
+        };
+
+        let duration_s = file.num_frames() as f32 / file.sample_rate as f32;
+        info!(
+            "Decoded {duration_s:.1}s of audio at {} Hz",
+            file.sample_rate
+        );
+        Ok(file)
+    }
+}
+
+/// Reads a decoded file front to back.
+pub struct FileSource {
+    file: AudioFile,
+    position: usize,
+}
+
+impl FileSource {
+    pub fn new(file: AudioFile) -> Self {
+        FileSource { file, position: 0 }
+    }
+}
+
+impl Source for FileSource {
+    fn read(&mut self, samples: &mut [f32]) -> usize {
+        let end = (self.position + samples.len()).min(self.file.samples.len());
+        let count = end - self.position;
+        samples[..count].copy_from_slice(&self.file.samples[self.position..end]);
+        self.position = end;
+        count
+    }
+}
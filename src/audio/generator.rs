@@ -0,0 +1,305 @@
+use std::f32::consts::PI;
+
+use super::{feeder::Source, file::AudioFile};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Signal {
+    /// Exponential sine sweep, repeated every `generator-ramp-s` seconds
+    Sweep,
+    WhiteNoise,
+    PinkNoise,
+    /// Click track with an accent on the first beat of every bar, see `generator-beats-per-bar`
+    Clicks,
+    /// Four-on-the-floor kick pattern with off-beat hi-hats
+    Kicks,
+    /// Kicks with hi-hats on the second and third triplet of every beat
+    Triplets,
+    /// Kicks with a bassline changing every bar and chords changing every eight bars
+    Song,
+    /// Song with an eight bar breakdown and build-up every 32 bars, the kick returns on the drop
+    Arrangement,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct GeneratorArgs {
+    /// Generate a test signal instead of reading the input device
+    #[arg(long, value_enum)]
+    pub generate: Option<Signal>,
+
+    /// Tempo of the generated click/kick pattern
+    #[arg(long, default_value = "128")]
+    pub generator_bpm: f32,
+
+    /// Ramp the tempo of the pattern linearly towards this BPM
+    #[arg(long)]
+    pub generator_bpm_end: Option<f32>,
+
+    /// Duration of the tempo ramp and of a single sine sweep
+    #[arg(long, default_value = "60")]
+    pub generator_ramp_s: f32,
+
+    /// Beats per bar of the click and song patterns
+    #[arg(long, default_value = "4", value_parser = clap::value_parser!(u64).range(1..))]
+    pub generator_beats_per_bar: u64,
+
+    /// Position of the off-beat within the beat: 0.5 is straight, 0.67 is triplet swing
+    #[arg(long, default_value = "0.5")]
+    pub generator_swing: f32,
+
+    #[arg(long, default_value = "20")]
+    pub sweep_from_fq: f32,
+    #[arg(long, default_value = "20000")]
+    pub sweep_to_fq: f32,
+}
+
+/// Synthesizes known-answer test signals (mono, written to both channels).
+pub struct Generator {
+    signal: Signal,
+    args: GeneratorArgs,
+    sample_rate: f32,
+    sample_index: u64,
+
+    noise_state: u32,
+    pink_state: [f32; 3],
+    oscillator_phase: f32,
+    chord_phases: [f32; 3],
+    riser_state: f32,
+
+    /// Position in beats. Starts slightly negative so that the first beat triggers at 0.
+    beat_phase: f64,
+    beat_count: u64,
+    /// Samples since the last trigger of the beat and off-beat sounds.
+    beat_age: Option<u32>,
+    off_beat_age: Option<u32>,
+}
+
+impl Generator {
+    /// Sounds are cut off after this duration.
+    const SOUND_S: f32 = 0.4;
+    /// Sounds are faded out before being cut off, the step would be detected as an onset.
+    const FADE_S: f32 = 0.05;
+    const BARS_PER_CHORD: u64 = 8;
+    const BARS_PER_ARRANGEMENT: u64 = 32;
+    const BREAKDOWN_BAR: u64 = 16;
+    const BUILD_UP_BAR: u64 = 24;
+    /// Bass notes, one per bar: A1, D2, C2, G1.
+    const BASS_FQS: [f32; 4] = [55.0, 73.42, 65.41, 49.0];
+    /// Triads, one per eight bars: A, D, C and G major.
+    const CHORD_FQS: [[f32; 3]; 4] = [
+        [220.0, 277.18, 329.63],
+        [293.66, 369.99, 440.0],
+        [261.63, 329.63, 392.0],
+        [196.0, 246.94, 293.66],
+    ];
+
+    pub fn new(signal: Signal, args: &GeneratorArgs, sample_rate: u32) -> Self {
+        Generator {
+            signal,
+            args: args.clone(),
+            sample_rate: sample_rate as f32,
+            sample_index: 0,
+            noise_state: 0x1234_5678,
+            pink_state: [0.0; 3],
+            oscillator_phase: 0.0,
+            chord_phases: [0.0; 3],
+            riser_state: 0.0,
+            beat_phase: -1e-9,
+            beat_count: 0,
+            beat_age: None,
+            off_beat_age: None,
+        }
+    }
+
+    /// Render the first `duration_s` seconds of the signal.
+    pub fn render(mut self, duration_s: f32) -> AudioFile {
+        let mut samples = vec![0.0; 2 * (duration_s * self.sample_rate) as usize];
+        self.read(&mut samples);
+        AudioFile {
+            sample_rate: self.sample_rate as u32,
+            samples,
+        }
+    }
+
+    fn time(&self) -> f32 {
+        self.sample_index as f32 / self.sample_rate
+    }
+
+    /// White noise in `[-1, 1]` (xorshift).
+    fn white(&mut self) -> f32 {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 17;
+        self.noise_state ^= self.noise_state << 5;
+        self.noise_state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    /// Pink noise using Paul Kellet's economy filter.
+    fn pink(&mut self) -> f32 {
+        let white = self.white();
+        let [b0, b1, b2] = &mut self.pink_state;
+        *b0 = 0.99765 * *b0 + white * 0.0990460;
+        *b1 = 0.96300 * *b1 + white * 0.2965164;
+        *b2 = 0.57000 * *b2 + white * 1.0526913;
+        0.25 * (*b0 + *b1 + *b2 + white * 0.1848)
+    }
+
+    fn sweep(&mut self) -> f32 {
+        let progress = (self.time() / self.args.generator_ramp_s).fract();
+        let ratio = self.args.sweep_to_fq / self.sweep_from_fq();
+        let frequency = self.sweep_from_fq() * ratio.powf(progress);
+        self.oscillator_phase = (self.oscillator_phase + frequency / self.sample_rate).fract();
+        0.5 * (2.0 * PI * self.oscillator_phase).sin()
+    }
+
+    fn sweep_from_fq(&self) -> f32 {
+        self.args.sweep_from_fq.max(1.0)
+    }
+
+    fn bpm(&self) -> f32 {
+        let start = self.args.generator_bpm;
+        let end = self.args.generator_bpm_end.unwrap_or(start);
+        let progress = (self.time() / self.args.generator_ramp_s).min(1.0);
+        start + (end - start) * progress
+    }
+
+    /// Advance the beat clock, (re)triggering beat and off-beat sounds.
+    fn advance_beat(&mut self) {
+        let previous = self.beat_phase;
+        self.beat_phase += f64::from(self.bpm()) / 60.0 / f64::from(self.sample_rate);
+
+        if self.beat_phase.floor() > previous.floor() {
+            self.beat_count += 1;
+            self.beat_age = Some(0);
+        }
+
+        let swing = [f64::from(self.args.generator_swing)];
+        let off_beats: &[f64] = match self.signal {
+            Signal::Triplets => &[1.0 / 3.0, 2.0 / 3.0],
+            _ => &swing,
+        };
+        for &position in off_beats {
+            if previous.fract() < position && self.beat_phase.fract() >= position {
+                self.off_beat_age = Some(0);
+            }
+        }
+    }
+
+    fn age_s(age: &mut Option<u32>, sample_rate: f32) -> Option<f32> {
+        let current = (*age)?;
+        let age_s = current as f32 / sample_rate;
+        *age = (age_s < Self::SOUND_S).then_some(current + 1);
+        Some(age_s)
+    }
+
+    fn fade_out(age_s: f32) -> f32 {
+        ((Self::SOUND_S - age_s) / Self::FADE_S).clamp(0.0, 1.0)
+    }
+
+    fn clicks(&mut self) -> f32 {
+        self.advance_beat();
+        let accent = self.beat_count % self.args.generator_beats_per_bar == 1;
+        let frequency = if accent { 1500.0 } else { 1000.0 };
+        Self::age_s(&mut self.beat_age, self.sample_rate).map_or(0.0, |t| {
+            0.8 * (2.0 * PI * frequency * t).sin() * (-200.0 * t).exp()
+        })
+    }
+
+    fn kicks(&mut self) -> f32 {
+        self.advance_beat();
+
+        // Sine with a falling pitch envelope, 150 Hz down to 50 Hz.
+        let kick = Self::age_s(&mut self.beat_age, self.sample_rate).map_or(0.0, |t| {
+            let phase = 50.0 * t + 100.0 * (1.0 - (-30.0 * t).exp()) / 30.0;
+            0.8 * (2.0 * PI * phase).sin() * (-8.0 * t).exp() * Self::fade_out(t)
+        });
+
+        // Short burst of noise.
+        let hat = match Self::age_s(&mut self.off_beat_age, self.sample_rate) {
+            Some(t) => 0.2 * self.white() * (-80.0 * t).exp(),
+            None => 0.0,
+        };
+
+        kick + hat
+    }
+
+    /// The first beat triggers at sample 0 and counts as beat 1.
+    fn bar(&self) -> u64 {
+        self.beat_count.saturating_sub(1) / self.args.generator_beats_per_bar
+    }
+
+    fn song(&mut self) -> f32 {
+        let drums = self.kicks();
+        drums + self.bass() + self.pad()
+    }
+
+    fn bass(&mut self) -> f32 {
+        let bar = self.bar();
+        let bass_fq = Self::BASS_FQS[bar as usize % Self::BASS_FQS.len()];
+        self.oscillator_phase = (self.oscillator_phase + bass_fq / self.sample_rate).fract();
+        0.3 * (2.0 * PI * self.oscillator_phase).sin()
+    }
+
+    fn pad(&mut self) -> f32 {
+        let chord = (self.bar() / Self::BARS_PER_CHORD) as usize % Self::CHORD_FQS.len();
+        let mut pad = 0.0;
+        for (phase, fq) in self.chord_phases.iter_mut().zip(Self::CHORD_FQS[chord]) {
+            *phase = (*phase + fq / self.sample_rate).fract();
+            pad += 0.05 * (2.0 * PI * *phase).sin();
+        }
+        pad
+    }
+
+    fn arrangement(&mut self) -> f32 {
+        let drums = self.kicks();
+        let bass = self.bass();
+        let pad = self.pad();
+
+        let bar = self.bar() % Self::BARS_PER_ARRANGEMENT;
+        if bar < Self::BREAKDOWN_BAR {
+            return drums + bass + pad;
+        }
+        if bar < Self::BUILD_UP_BAR {
+            return 2.0 * pad;
+        }
+
+        // Noise riser, getting louder and brighter towards the drop.
+        let beats_per_bar = self.args.generator_beats_per_bar as f64;
+        let build_up_beats =
+            (Self::BARS_PER_ARRANGEMENT - Self::BUILD_UP_BAR) as f64 * beats_per_bar;
+        let beat_in_build_up = self
+            .beat_phase
+            .rem_euclid(Self::BARS_PER_ARRANGEMENT as f64 * beats_per_bar)
+            - Self::BUILD_UP_BAR as f64 * beats_per_bar;
+        let progress = (beat_in_build_up / build_up_beats).clamp(0.0, 1.0) as f32;
+        let cutoff_fq = 200.0 * 40f32.powf(progress);
+        let coefficient = 1.0 - (-2.0 * PI * cutoff_fq / self.sample_rate).exp();
+        let white = self.white();
+        self.riser_state += coefficient * (white - self.riser_state);
+        let riser = (0.1 + 0.5 * progress) * self.riser_state / coefficient.sqrt();
+
+        2.0 * pad + riser
+    }
+
+    fn sample(&mut self) -> f32 {
+        let x = match self.signal {
+            Signal::Sweep => self.sweep(),
+            Signal::WhiteNoise => 0.5 * self.white(),
+            Signal::PinkNoise => self.pink(),
+            Signal::Clicks => self.clicks(),
+            Signal::Kicks | Signal::Triplets => self.kicks(),
+            Signal::Song => self.song(),
+            Signal::Arrangement => self.arrangement(),
+        };
+        self.sample_index += 1;
+        x
+    }
+}
+
+impl Source for Generator {
+    fn read(&mut self, samples: &mut [f32]) -> usize {
+        for frame in samples.chunks_exact_mut(2) {
+            let x = self.sample();
+            frame.fill(x);
+        }
+        samples.len()
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{ops::Deref, path::Path};
 
 use tracing::{debug, error, instrument, warn};
 
@@ -9,10 +9,19 @@ use crate::{
     thread_shared::ThreadShared,
 };
 
-use self::{routing::Routing, stereo::Stereo};
+use self::{
+    feeder::{Feeder, Source},
+    file::{AudioFile, FileSource},
+    generator::{Generator, GeneratorArgs},
+    routing::Routing,
+    stereo::Stereo,
+};
 
+mod feeder;
+pub mod file;
+pub mod generator;
 mod routing;
-mod stereo;
+pub mod stereo;
 mod virtual_sink;
 
 fn choose_stream_config<ConfigsIter: Iterator<Item = cpal::SupportedStreamConfigRange>>(
@@ -284,11 +293,15 @@ impl Drop for DelayedOutput {
     }
 }
 
+enum Input {
+    Device(#[allow(dead_code)] cpal::Stream),
+    Source(Feeder),
+}
+
 pub struct Audio {
     cpal: Cpal,
     stereo: ThreadShared<stereo::Stereo>,
-    #[allow(dead_code)]
-    input_stream: cpal::Stream,
+    input: Input,
     #[allow(dead_code)]
     delayed_output: Option<DelayedOutput>,
 }
@@ -335,8 +348,68 @@ impl Audio {
         Ok(Audio {
             cpal,
             stereo: ring_buffer,
-            input_stream,
+            input: Input::Device(input_stream),
             delayed_output,
         })
     }
+
+    fn from_source(
+        source: Box<dyn Source>,
+        sample_rate: u32,
+        seconds: f32,
+        realtime: bool,
+    ) -> Self {
+        let mut cpal = Cpal::new();
+        cpal.sample_rate = sample_rate;
+
+        let buffer_size = seconds * cpal.sample_rate as f32;
+        let ring_buffer = ThreadShared::new(stereo::Stereo::new(buffer_size as usize));
+        let feeder = Feeder::new(source, sample_rate, &ring_buffer, realtime);
+
+        Audio {
+            cpal,
+            stereo: ring_buffer,
+            input: Input::Source(feeder),
+            delayed_output: None,
+        }
+    }
+
+    /// Read the audio from a file instead of the default input device. When `realtime` is not
+    /// set, the file is fed only when calling `on_tick`, as fast as the mainloop runs.
+    #[instrument(name = "Audio::from_file")]
+    pub fn from_file(path: &Path, seconds: f32, realtime: bool) -> VResult<Self> {
+        let file = AudioFile::decode(path)?;
+        let sample_rate = file.sample_rate;
+        let source = Box::new(FileSource::new(file));
+        Ok(Self::from_source(source, sample_rate, seconds, realtime))
+    }
+
+    /// Generate a test signal instead of reading the default input device.
+    pub fn from_generator(
+        signal: generator::Signal,
+        args: &GeneratorArgs,
+        seconds: f32,
+        realtime: bool,
+    ) -> Self {
+        let sample_rate = Cpal::new().sample_rate;
+        let source = Box::new(Generator::new(signal, args, sample_rate));
+        Self::from_source(source, sample_rate, seconds, realtime)
+    }
+
+    /// Feed the next chunk of a non-realtime input. Writes at most half the buffer at once, so
+    /// that the analysis can consume everything before it gets overwritten.
+    pub fn on_tick(&mut self) {
+        if let Input::Source(feeder) = &mut self.input {
+            let max_frames = self.stereo.read().left.size / 2;
+            feeder.pump(&self.stereo, max_frames);
+        }
+    }
+
+    /// Whether a file input has been fed completely. Device inputs never finish.
+    pub fn finished(&self) -> bool {
+        match &self.input {
+            Input::Device(_) => false,
+            Input::Source(feeder) => feeder.finished(),
+        }
+    }
 }
@@ -20,6 +20,9 @@ pub enum Error {
     Cpal(Cpal),
     Libpulse(libpulse_binding::error::PAErr),
     Shaderc(shaderc::Error),
+    Wav(hound::Error),
+    Flac(claxon::Error),
+    Json(serde_json::Error),
 }
 
 pub type VResult<T> = Result<T, Error>;
@@ -37,6 +40,9 @@ impl Display for Error {
             Error::Cpal(error) => write!(f, "CPAL Error\n{error:?}"),
             Error::Libpulse(error) => write!(f, "Pulse Error\n{error:?}"),
             Error::Shaderc(error) => write!(f, "Shaderc Error\n{error:?}"),
+            Error::Wav(error) => write!(f, "WAV Error\n{error}"),
+            Error::Flac(error) => write!(f, "FLAC Error\n{error}"),
+            Error::Json(error) => write!(f, "JSON Error\n{error}"),
         }
     }
 }
@@ -94,3 +100,21 @@ impl From<shaderc::Error> for Error {
         Self::Shaderc(value)
     }
 }
+
+impl From<hound::Error> for Error {
+    fn from(value: hound::Error) -> Self {
+        Self::Wav(value)
+    }
+}
+
+impl From<claxon::Error> for Error {
+    fn from(value: claxon::Error) -> Self {
+        Self::Flac(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::ring_buffer::RingBuffer;
+
+use super::{alpha_avg::AlphaAvg, filter::Filter};
+
+/// What the gain of the `Agc` is derived from.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Detector {
+    /// The absolute samples, peaks end up around 1
+    Peak,
+    /// The power averaged over a beat, the RMS ends up around 1/4 unless the peaks exceed 1
+    Rms,
+}
+
+/// Automatic gain control. The level of the input is followed quickly when it rises (attack) and
+/// slowly when it falls (release), and the gain is applied to the input delayed by the lookahead.
+/// That way the gain is already reduced when a transient leaves the filter, and it changes slowly
+/// enough not to distort the waveform.
+pub struct Agc {
+    detector: Detector,
+    power: AlphaAvg,
+    attack: f32,
+    release: f32,
+    lookahead: RingBuffer<f32>,
+    peak_envelope: f32,
+    rms_envelope: f32,
+
+    pub gain: f32,
+}
+
+impl Agc {
+    /// Averaging time of the power for the RMS detector.
+    const RMS_S: f32 = 0.4;
+    const RMS_TARGET: f32 = 0.25;
+    /// Levels below this are not amplified any further, so that noise stays quiet.
+    const MIN_LEVEL: f32 = 0.05;
+
+    pub fn new(
+        sample_rate: f32,
+        detector: Detector,
+        attack_s: f32,
+        release_s: f32,
+        lookahead_s: f32,
+    ) -> Self {
+        let coefficient = |time_s: f32| (-1.0 / (time_s * sample_rate)).exp();
+        let lookahead = ((lookahead_s * sample_rate) as usize).max(1);
+        Self {
+            detector,
+            power: AlphaAvg::new(coefficient(Self::RMS_S)),
+            attack: coefficient(attack_s),
+            release: coefficient(release_s),
+            lookahead: RingBuffer::new(lookahead),
+            peak_envelope: 0.0,
+            rms_envelope: 0.0,
+
+            gain: 1.0,
+        }
+    }
+
+    /// Forget the level of the past and start over from the level of the samples in the
+    /// lookahead, which are yet to be amplified.
+    pub fn reset(&mut self) {
+        let samples = &self.lookahead.data;
+        self.peak_envelope = samples.iter().fold(0.0, |peak, x| x.abs().max(peak));
+        self.power.avg = samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32;
+        self.rms_envelope = self.power.avg.sqrt();
+    }
+
+    fn follow(&self, envelope: f32, level: f32) -> f32 {
+        let coefficient = if level > envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        coefficient * envelope + (1.0 - coefficient) * level
+    }
+}
+
+impl Filter for Agc {
+    fn sample(&mut self, x: f32) -> f32 {
+        let delayed = self.lookahead.oldest();
+        self.lookahead.push(x);
+
+        self.peak_envelope = self.follow(self.peak_envelope, x.abs());
+        let peak_gain = 1.0 / self.peak_envelope.max(Self::MIN_LEVEL);
+        self.gain = match self.detector {
+            Detector::Peak => peak_gain,
+            Detector::Rms => {
+                // The average lags behind sudden rises, the peaks limit the gain until then.
+                let rms = self.power.sample(x * x).sqrt();
+                self.rms_envelope = self.follow(self.rms_envelope, rms);
+                let rms_gain = Self::RMS_TARGET / self.rms_envelope.max(Self::MIN_LEVEL);
+                rms_gain.min(peak_gain)
+            }
+        };
+        self.gain * delayed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+    const LOOKAHEAD_S: f32 = 0.005;
+
+    fn sine(index: usize) -> f32 {
+        0.1 * (2.0 * PI * 440.0 * index as f32 / SAMPLE_RATE).sin()
+    }
+
+    /// Feed `num_samples` of the sine from `start` on and return the largest deviation from the
+    /// sine, delayed by the lookahead and scaled to `amplitude`.
+    fn max_deviation(agc: &mut Agc, start: usize, num_samples: usize, amplitude: f32) -> f32 {
+        let delay = (LOOKAHEAD_S * SAMPLE_RATE) as usize;
+        (start..start + num_samples)
+            .map(|index| {
+                let expected = amplitude / 0.1 * sine(index - delay);
+                (agc.sample(sine(index)) - expected).abs()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn keeps_the_shape_of_a_steady_sine() {
+        for (detector, amplitude) in [
+            (Detector::Peak, 1.0),
+            (Detector::Rms, Agc::RMS_TARGET * 2f32.sqrt()),
+        ] {
+            let mut agc = Agc::new(SAMPLE_RATE, detector, 0.001, 6.0, LOOKAHEAD_S);
+            let settle = (2.0 * SAMPLE_RATE) as usize;
+            (0..settle).for_each(|index| {
+                agc.sample(sine(index));
+            });
+
+            let second = SAMPLE_RATE as usize;
+            let deviation = max_deviation(&mut agc, settle, second, amplitude);
+            assert!(
+                deviation < 0.05 * amplitude,
+                "{detector:?}: deviation {deviation}"
+            );
+
+            agc.reset();
+            let deviation = max_deviation(&mut agc, settle + second, second, amplitude);
+            assert!(
+                deviation < 0.05 * amplitude,
+                "{detector:?}: deviation {deviation} after reset"
+            );
+        }
+    }
+}
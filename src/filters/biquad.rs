@@ -0,0 +1,80 @@
+use std::f32::consts::PI;
+
+use super::filter::Filter;
+
+/// Second order IIR filter, with the designs of the Audio EQ Cookbook.
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn new(b: [f32; 3], a: [f32; 3]) -> Self {
+        Self {
+            b0: b[0] / a[0],
+            b1: b[1] / a[0],
+            b2: b[2] / a[0],
+            a1: a[1] / a[0],
+            a2: a[2] / a[0],
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// Amplifies the frequencies above `fq` by `gain_db`.
+    pub fn high_shelf(sample_rate: f32, fq: f32, q: f32, gain_db: f32) -> Self {
+        let a = 10f32.powf(gain_db / 40.0);
+        let omega = 2.0 * PI * fq / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+        Self::new(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+            ],
+        )
+    }
+
+    pub fn high_pass(sample_rate: f32, fq: f32, q: f32) -> Self {
+        let omega = 2.0 * PI * fq / sample_rate;
+        let alpha = omega.sin() / (2.0 * q);
+        let cos = omega.cos();
+
+        Self::new(
+            [0.5 * (1.0 + cos), -(1.0 + cos), 0.5 * (1.0 + cos)],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+}
+
+impl Filter for Biquad {
+    fn sample(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        y
+    }
+}
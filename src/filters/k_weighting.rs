@@ -0,0 +1,23 @@
+use super::{biquad::Biquad, filter::Filter};
+
+/// The frequency weighting of ITU-R BS.1770 loudness: a shelf for the acoustic effect of the head,
+/// followed by a high pass for the insensitivity of the ear to low frequencies.
+pub struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            shelf: Biquad::high_shelf(sample_rate, 1500.0, 1.0 / 2f32.sqrt(), 4.0),
+            high_pass: Biquad::high_pass(sample_rate, 38.0, 0.5),
+        }
+    }
+}
+
+impl Filter for KWeighting {
+    fn sample(&mut self, x: f32) -> f32 {
+        self.high_pass.sample(self.shelf.sample(x))
+    }
+}
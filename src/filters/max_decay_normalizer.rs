@@ -14,11 +14,21 @@ impl MaxDecayNormalizer {
             max: 0f32,
         }
     }
+
+    /// Forget the maximum, the next sample is normalized to 1.
+    pub fn reset(&mut self) {
+        self.max = 0f32;
+    }
+
+    /// Follow `level` instead of the samples, e.g. a loudness converted to an amplitude.
+    pub fn normalize_to(&mut self, x: f32, level: f32) -> f32 {
+        self.max = (self.max * self.alpha).max(level).max(self.min_max);
+        x / self.max
+    }
 }
 
 impl Filter for MaxDecayNormalizer {
     fn sample(&mut self, x: f32) -> f32 {
-        self.max = (self.max * self.alpha).max(x).max(self.min_max);
-        x / self.max
+        self.normalize_to(x, x)
     }
 }
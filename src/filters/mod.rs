@@ -1,8 +1,11 @@
+pub mod agc;
 pub mod alpha_avg;
+pub mod biquad;
 pub mod biquad_band_pass;
 pub mod energy;
 pub mod filter;
 pub mod high_pass;
+pub mod k_weighting;
 pub mod low_pass;
 pub mod max_decay_normalizer;
 pub mod statistical_summary;
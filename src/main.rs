@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser};
 
 mod analysis;
 mod audio;
@@ -17,9 +17,19 @@ use winit::{
     event::VirtualKeyCode, event_loop::ControlFlow, platform::run_return::EventLoopExtRunReturn,
 };
 
+#[derive(clap::Subcommand, Debug, Clone)]
+pub enum Command {
+    Analyze(analysis::offline::AnalyzeArgs),
+    Evaluate(analysis::evaluation::EvaluateArgs),
+}
+
 /// Run an audio visualizer.
 #[derive(Parser, Debug, Clone)]
 pub struct Args {
+    /// Run an offline tool instead of the visualizer
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The shader module path
     #[arg(short, long, num_args = 0.., default_values = &["shaders/paint.comp", "shaders/present.comp"])]
     shader_paths: Vec<std::path::PathBuf>,
@@ -36,6 +46,11 @@ pub struct Args {
     #[arg(long, action = clap::ArgAction::SetTrue)]
     no_vsync: bool,
 
+    /// Time from rendering a frame until it is on screen, the beat is predicted that far ahead.
+    /// Estimated from the frame rate and the swapchain length if not given
+    #[arg(long)]
+    display_latency_ms: Option<f32>,
+
     /// Redirect the audio through a virtual pulseaudio sink
     #[arg(long, action = clap::ArgAction::SetTrue)]
     no_virtual_sink: bool,
@@ -48,15 +63,86 @@ pub struct Args {
     #[arg(long, action = clap::ArgAction::SetTrue)]
     headless: bool,
 
-    #[arg(long, default_value = "110")]
+    /// Read audio from a WAV/FLAC file instead of the default input device
+    #[arg(short, long)]
+    input_file: Option<std::path::PathBuf>,
+
+    #[command(flatten)]
+    generator: audio::generator::GeneratorArgs,
+
+    /// Feed the input file or generator as fast as the analysis consumes it instead of in real time
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    no_realtime: bool,
+
+    /// Samples to analyze per tick: 'elapsed' (wall clock), 'available' or a fixed count
+    #[arg(long)]
+    pacing: Option<analysis::clock::Pacing>,
+
+    /// How onsets are detected before being fed into the BPM tracker
+    #[arg(long, value_enum, default_value = "bass-energy")]
+    onset_detector: analysis::onset_detector::OnsetDetectorKind,
+
+    /// Pin the center frequency of the bass band in Hz, instead of selecting the band with the
+    /// most periodic onsets among 40-125 Hz
+    #[arg(long)]
+    bass_fq: Option<usize>,
+
+    /// Q of the bass band (default 6)
+    #[arg(long)]
+    bass_q: Option<f32>,
+
+    /// How the onsets are turned into a beat grid
+    #[arg(long, value_enum, default_value = "grid")]
+    beat_tracker: analysis::beat_tracker::BeatTrackerKind,
+
+    /// Which tempo estimate the BPM tracker follows
+    #[arg(long, value_enum, default_value = "combined")]
+    tempo_estimator: analysis::bpm_tracker::TempoEstimator,
+
+    /// Beats per bar, or 'auto' to detect it from the music
+    #[arg(long, value_enum, default_value = "4/4")]
+    meter: analysis::bar_tracker::Meter,
+
+    /// Input level below which the signal counts as silent, in dBFS
+    #[arg(long, default_value = "-60")]
+    silence_gate_db: f32,
+
+    /// Seconds of silence after which the trackers are reset once the signal returns
+    #[arg(long, default_value = "2")]
+    silence_s: f32,
+
+    /// How the input is normalized before the analysis, 'max-decay' is the former normalizer
+    #[arg(long, value_enum, default_value = "agc")]
+    normalizer: analysis::Normalizer,
+
+    /// What the gain of the AGC is derived from
+    #[arg(long, value_enum, default_value = "rms")]
+    agc_detector: filters::agc::Detector,
+
+    /// What the max-decay normalizer follows, 'loudness' keeps single loud transients from ducking
+    /// everything else. Requires '--normalizer max-decay'
+    #[arg(long, value_enum, default_value = "peak")]
+    normalize_to: analysis::loudness::NormalizerLevel,
+
+    #[arg(long, default_value = "60")]
     slowest_bpm: u32,
-    #[arg(long, default_value = "160")]
+    #[arg(long, default_value = "200")]
     fastest_bpm: u32,
 }
 
 fn run_main(args: &Args) -> error::VResult<()> {
     // Audio launches its own pulseaudio something threads, no ticking required.
-    let audio = audio::Audio::new(args.audio_buffer_sec, !args.no_virtual_sink)?;
+    // Non-realtime file inputs are fed on tick.
+    let mut audio = match (&args.input_file, args.generator.generate) {
+        (Some(path), _) => audio::Audio::from_file(path, args.audio_buffer_sec, !args.no_realtime)?,
+        (None, Some(signal)) => audio::Audio::from_generator(
+            signal,
+            &args.generator,
+            args.audio_buffer_sec,
+            !args.no_realtime,
+        ),
+        (None, None) => audio::Audio::new(args.audio_buffer_sec, !args.no_virtual_sink)?,
+    };
 
     // The websocket server launches a tokio runtime and listens to a channel.
     // No ticking apart from populating the channel is required.
@@ -83,9 +169,12 @@ fn run_main(args: &Args) -> error::VResult<()> {
     // Choose the mainloop.
     if args.headless {
         // Use a custom headless mainloop.
-        while run.load(std::sync::atomic::Ordering::SeqCst) {
-            analysis.as_mut_ref().on_tick(&audio.signal);
-            utils::sleep_ms(16);
+        while run.load(std::sync::atomic::Ordering::SeqCst) && !audio.finished() {
+            audio.on_tick();
+            analysis.as_mut_ref().on_tick(&audio);
+            if !args.no_realtime {
+                utils::sleep_ms(16);
+            }
         }
     } else {
         // The visualizer should be ticked once per frame.
@@ -102,8 +191,14 @@ fn run_main(args: &Args) -> error::VResult<()> {
             *control_flow = match window::translate_event(event) {
                 // No other events, run analysis and render a frame.
                 window::Event::Tick => {
-                    analysis.as_mut_ref().on_tick(&audio.signal);
-                    match visualizer.as_mut_ref().tick(&analysis.as_ref()) {
+                    audio.on_tick();
+                    analysis.as_mut_ref().on_tick(&audio);
+                    let result = visualizer.as_mut_ref().tick(&analysis.as_ref());
+                    let latency_s = visualizer.as_ref().display_latency_s();
+                    analysis
+                        .as_mut_ref()
+                        .set_measured_display_latency(latency_s);
+                    match result {
                         Ok(()) => ControlFlow::Poll,
                         Err(err) => {
                             tracing::error!("Running vulkan tick failed: {err}");
@@ -119,6 +214,11 @@ fn run_main(args: &Args) -> error::VResult<()> {
                     ControlFlow::ExitWithCode(0)
                 }
 
+                window::Event::Manual(action) => {
+                    analysis.as_mut_ref().on_manual_action(action);
+                    ControlFlow::Poll
+                }
+
                 // Resize events can originate from both winit and vulkan.... Register the resize
                 // event and wait until no resize events were recieved for X seconds.
                 window::Event::Resize(width, height) => {
@@ -148,12 +248,28 @@ fn main() {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_timer(CustomTime)
         .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::io::stderr)
         .finish();
     tracing::subscriber::set_global_default(subscriber).expect("Failed to set global subscriber");
 
     tracing::info!("Starting visualize-rs...");
     let args = Args::parse();
-    if let Err(err) = run_main(&args) {
+    if args.normalizer == analysis::Normalizer::Agc
+        && args.normalize_to == analysis::loudness::NormalizerLevel::Loudness
+    {
+        let msg = "'--normalize-to loudness' only applies to '--normalizer max-decay'";
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, msg)
+            .exit();
+    }
+    let result = match &args.command {
+        Some(Command::Analyze(analyze_args)) => analysis::offline::analyze(&args, analyze_args),
+        Some(Command::Evaluate(evaluate_args)) => {
+            analysis::evaluation::evaluate(&args, evaluate_args)
+        }
+        None => run_main(&args),
+    };
+    if let Err(err) = result {
         tracing::error!("{}", err);
     }
     tracing::info!("Stopping visualize-rs...");
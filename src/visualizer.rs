@@ -11,8 +11,9 @@ use tracing::{debug, span, Level};
 use winit::event_loop;
 
 use crate::{
-    analysis::Analysis,
+    analysis::{chroma::Chroma, Analysis},
     error::{Error, VResult},
+    filters::{alpha_avg::AlphaAvg, filter::Filter},
     ring_buffer,
     utils::sleep_ms,
     vulkan::{self, multi_buffer, multi_image, Vulkan},
@@ -58,10 +59,17 @@ impl PushConstants {
 pub struct Visualizer {
     bass_signal_gpu: Rc<multi_buffer::MultiBuffer>,
     signal_dft_gpu: Rc<multi_buffer::MultiBuffer>,
+    harmonic_dft_gpu: Rc<multi_buffer::MultiBuffer>,
+    chroma_gpu: Rc<multi_buffer::MultiBuffer>,
 
     new_resolution: Option<vk::Extent2D>,
     last_resized_time: Instant,
 
+    vsync: bool,
+    last_tick: Instant,
+    /// Average time between frames in seconds.
+    frame_interval: AlphaAvg,
+
     // These should be dropped last.
     images: Vec<Rc<multi_image::MultiImage>>,
     vulkan: Vulkan,
@@ -114,6 +122,11 @@ impl Visualizer {
             let size = analysis.signal_dft.log_bin_serialized_size();
             vulkan.new_multi_buffer("signal_dft", size, Some(1))?
         };
+        let harmonic_dft_gpu = {
+            let size = analysis.signal_dft.log_bin_serialized_size();
+            vulkan.new_multi_buffer("harmonic_dft", size, Some(1))?
+        };
+        let chroma_gpu = vulkan.new_multi_buffer("chroma", Chroma::serialized_size(), Some(1))?;
         // let low_pass_dft_gpu = {
         //     let size = analysis.low_pass_dft.serialized_size();
         //     vulkan.new_multi_buffer("low_pass_dft", size, Some(1))?
@@ -126,12 +139,17 @@ impl Visualizer {
         let mut visualizer = Self {
             bass_signal_gpu,
             signal_dft_gpu,
+            harmonic_dft_gpu,
+            chroma_gpu,
             // low_pass_gpu,
             // low_pass_dft_gpu,
             // high_pass_gpu,
             // high_pass_dft_gpu,
             new_resolution: None,
             last_resized_time: Instant::now(),
+            vsync: !args.no_vsync,
+            last_tick: Instant::now(),
+            frame_interval: AlphaAvg::new_with_value(0.95, 1.0 / 60.0),
             images: Vec::new(),
             vulkan,
         };
@@ -169,7 +187,26 @@ impl Visualizer {
         Ok(())
     }
 
+    /// Estimated time from rendering a frame until it is on screen: with vsync the frames queued
+    /// in the swapchain are shown first, then the scanout takes another half frame on average.
+    pub fn display_latency_s(&self) -> f32 {
+        let queued_frames = if self.vsync {
+            self.vulkan
+                .surface_info
+                .desired_image_count
+                .saturating_sub(1)
+        } else {
+            0
+        };
+        (queued_frames as f32 + 0.5) * self.frame_interval.avg
+    }
+
     pub fn tick(&mut self, analysis: &Analysis) -> VResult<()> {
+        let now = Instant::now();
+        self.frame_interval
+            .sample(now.duration_since(self.last_tick).as_secs_f32());
+        self.last_tick = now;
+
         if self.new_resolution.is_some() {
             // Don't render anything.
             sleep_ms(16);
@@ -187,6 +224,11 @@ impl Visualizer {
         analysis
             .signal_dft
             .write_log_bins_to_pointer(self.signal_dft_gpu.mapped(0));
+        analysis.signal_dft.write_spectrum_log_bins_to_pointer(
+            &analysis.hpss.harmonic,
+            self.harmonic_dft_gpu.mapped(0),
+        );
+        analysis.chroma.write_to_pointer(self.chroma_gpu.mapped(0));
 
         analysis.bass_energy.write_to_pointer(
             read_index,
@@ -198,20 +240,87 @@ impl Visualizer {
         let mut push_constants = PushConstants::new();
 
         push_constants.u32("frame_index", self.vulkan.num_frames as u32);
-        push_constants.f32("time", analysis.epoch.elapsed().as_secs_f32());
+        push_constants.f32("time", analysis.time());
 
-        let bass = &analysis.beat_detector.energy;
-        push_constants.f32("bass_energy", bass.last());
-        push_constants.f32("cumulative_bass_energy", bass.cumulative());
+        let bass = &analysis.beat_detector;
+        push_constants.f32("bass_energy", bass.energy());
+        push_constants.f32("cumulative_bass_energy", bass.cumulative_energy());
 
+        // Detected onsets can't be predicted, they are shown as soon as possible.
         push_constants.bool("is_beat", analysis.beat_in_tick);
         push_constants.u32("real_beats", analysis.real_beats);
 
+        let percussion = &analysis.percussion;
+        let [kick, snare, hihat] = percussion.energies();
+        push_constants.bool("is_kick", percussion.kick_in_tick);
+        push_constants.bool("is_snare", percussion.snare_in_tick);
+        push_constants.bool("is_hihat", percussion.hihat_in_tick);
+        push_constants.f32("kick_energy", kick);
+        push_constants.f32("snare_energy", snare);
+        push_constants.f32("hihat_energy", hihat);
+
         let confidence = analysis.bpm_tracker.bpm_confidence();
         push_constants.f32("bpm_confidence", confidence);
-        push_constants.f32("bpm_period", analysis.bpm_tracker.bpm.period);
-        push_constants.u32("beat_index", analysis.fake_beats);
-        push_constants.f32("beat_fract", analysis.beat_fract);
+        push_constants.f32("bpm_period", analysis.bpm_tracker.bpm().period);
+        // The grid as it will be when the frame is on screen.
+        let predicted = &analysis.predicted;
+        push_constants.u32("beat_index", predicted.beat_index);
+        push_constants.f32("beat_fract", predicted.beat_fract);
+        push_constants.bool("is_grid_beat", predicted.is_beat);
+        push_constants.f32("next_beat_s", predicted.next_beat_s);
+
+        // Eighths = 0, sixteenths = 1, triplets = 2.
+        let groove = &analysis.groove;
+        push_constants.u32("subdivision", groove.subdivision as u32);
+        push_constants.u32("subdivision_in_beat", groove.subdivision_in_beat);
+        push_constants.f32("subdivision_fract", groove.subdivision_fract);
+        push_constants.f32("swing", groove.swing);
+
+        let bars = &analysis.bar_tracker;
+        push_constants.u32("beats_per_bar", bars.beats_per_bar);
+        let beats_ahead = predicted.beats_ahead(analysis.fake_beats);
+        let beat_in_bar = (bars.beat_in_bar + beats_ahead) % bars.beats_per_bar.max(1);
+        push_constants.u32("beat_in_bar", beat_in_bar);
+        push_constants.u32("bar_index", bars.bar_index);
+        push_constants.f32("downbeat_confidence", bars.downbeat_confidence);
+        for phrase in &bars.phrases {
+            let length = phrase.length;
+            push_constants.u32(&format!("phrase_{length}_bar"), phrase.bar);
+            push_constants.f32(&format!("phrase_{length}_confidence"), phrase.confidence);
+        }
+
+        // Steady = 0, breakdown = 1, build-up = 2, drop = 3.
+        let sections = &analysis.sections;
+        push_constants.u32("section", sections.section as u32);
+        push_constants.f32("build_up_progress", sections.build_up_progress);
+        push_constants.bool("is_drop", sections.drop_in_tick);
+
+        // MIDI note 0 and frequency 0 while unvoiced.
+        let pitch = &analysis.pitch;
+        push_constants.f32("pitch_fq", pitch.fq);
+        push_constants.f32("pitch_note", pitch.note);
+        push_constants.f32("pitch_confidence", pitch.confidence);
+
+        // Centroid, spread and rolloff in Hz.
+        let descriptors = &analysis.descriptors;
+        push_constants.f32("spectral_centroid", descriptors.centroid);
+        push_constants.f32("spectral_spread", descriptors.spread);
+        push_constants.f32("spectral_rolloff", descriptors.rolloff);
+        push_constants.f32("spectral_flatness", descriptors.flatness);
+        push_constants.f32("spectral_flux", descriptors.flux);
+        push_constants.f32("zero_crossing_rate", descriptors.zero_crossing_rate);
+
+        // Of the input before normalization, in dBFS and LUFS.
+        let loudness = &analysis.loudness;
+        push_constants.f32("rms_left_db", loudness.rms_db[0]);
+        push_constants.f32("rms_right_db", loudness.rms_db[1]);
+        push_constants.f32("true_peak_left_db", loudness.true_peak_db[0]);
+        push_constants.f32("true_peak_right_db", loudness.true_peak_db[1]);
+        push_constants.f32("momentary_lufs", loudness.momentary_lufs);
+        push_constants.f32("short_term_lufs", loudness.short_term_lufs);
+
+        push_constants.bool("no_signal", analysis.track_monitor.no_signal);
+        push_constants.bool("tempo_locked", analysis.manual.locked);
 
         // Actually render sth.
         if let Err(Error::Vk(vk::Result::ERROR_OUT_OF_DATE_KHR)) =
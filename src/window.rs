@@ -4,12 +4,14 @@ use ash::vk::SurfaceKHR as VkSurface;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use winit::{
     dpi::PhysicalSize,
-    event::{self, ElementState, KeyboardInput, WindowEvent},
+    event::{self, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::EventLoop,
     window::{Window as WinitWindow, WindowBuilder},
 };
 
-use crate::{error::VResult, vulkan::resources::instance::Instance};
+use crate::{
+    analysis::manual::ManualAction, error::VResult, vulkan::resources::instance::Instance,
+};
 
 pub struct Window(WinitWindow);
 
@@ -53,10 +55,25 @@ pub enum Event {
     Tick,
     Close,
     KeyPress(event::VirtualKeyCode),
+    Manual(ManualAction),
     Resize(u32, u32),
     Other,
 }
 
+/// Space taps the beat, the arrows nudge the grid (left/right) and halve/double the tempo
+/// (down/up), L locks the automatic tracking.
+fn manual_action(key: VirtualKeyCode) -> Option<ManualAction> {
+    match key {
+        VirtualKeyCode::Space => Some(ManualAction::Tap),
+        VirtualKeyCode::Right => Some(ManualAction::NudgeForward),
+        VirtualKeyCode::Left => Some(ManualAction::NudgeBack),
+        VirtualKeyCode::Down => Some(ManualAction::HalveTempo),
+        VirtualKeyCode::Up => Some(ManualAction::DoubleTempo),
+        VirtualKeyCode::L => Some(ManualAction::ToggleLock),
+        _ => None,
+    }
+}
+
 pub fn translate_event(event: event::Event<()>) -> Event {
     match event {
         event::Event::WindowEvent {
@@ -75,7 +92,10 @@ pub fn translate_event(event: event::Event<()>) -> Event {
                     ..
                 },
             ..
-        } => Event::KeyPress(key),
+        } => match manual_action(key) {
+            Some(action) => Event::Manual(action),
+            None => Event::KeyPress(key),
+        },
 
         // match key {
         //     VirtualKeyCode::Escape | VirtualKeyCode::Q => ControlFlow::Exit,
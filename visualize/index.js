@@ -142,16 +142,56 @@ function plotSimple(value, add) {
 
 // initializeGraphics(floats => floats, plotSimple);
 
+const FRAME_SIZE = 44;
+
 function floatsToEnergyStats(floats) {
   const results = [];
-  for (let i = 0; i < floats.length; i += 6) {
+  for (let i = 0; i < floats.length; i += FRAME_SIZE) {
     results.push({
       energy: floats[i + 0],
       short: floats[i + 1],
       long: floats[i + 2],
       is_beat: floats[i + 3] > 0.5,
       confidence: floats[i + 4],
-      phase_error: floats[i + 5]
+      phase_error: floats[i + 5],
+      kick: floats[i + 6],
+      snare: floats[i + 7],
+      hihat: floats[i + 8],
+      is_kick: floats[i + 9] > 0.5,
+      is_snare: floats[i + 10] > 0.5,
+      is_hihat: floats[i + 11] > 0.5,
+      beat_in_bar: floats[i + 12],
+      bar_index: floats[i + 13],
+      downbeat_confidence: floats[i + 14],
+      phrase_8_bar: floats[i + 15],
+      phrase_16_bar: floats[i + 16],
+      phrase_32_bar: floats[i + 17],
+      phrase_confidence: floats[i + 18],
+      beats_per_bar: floats[i + 19],
+      section: floats[i + 20],
+      build_up_progress: floats[i + 21],
+      no_signal: floats[i + 22] > 0.5,
+      subdivision: floats[i + 23],
+      swing: floats[i + 24],
+      subdivision_fract: floats[i + 25],
+      key_tonic: floats[i + 26],
+      key_is_minor: floats[i + 27] > 0.5,
+      key_confidence: floats[i + 28],
+      pitch_fq: floats[i + 29],
+      pitch_note: floats[i + 30],
+      pitch_confidence: floats[i + 31],
+      spectral_centroid: floats[i + 32],
+      spectral_spread: floats[i + 33],
+      spectral_rolloff: floats[i + 34],
+      spectral_flatness: floats[i + 35],
+      spectral_flux: floats[i + 36],
+      zero_crossing_rate: floats[i + 37],
+      rms_left_db: floats[i + 38],
+      rms_right_db: floats[i + 39],
+      true_peak_left_db: floats[i + 40],
+      true_peak_right_db: floats[i + 41],
+      momentary_lufs: floats[i + 42],
+      short_term_lufs: floats[i + 43]
     });
   }
   return results;
@@ -161,7 +201,7 @@ const nmin = (a, b) => a < b ? a : b;
 const nmax = (a, b) => a > b ? a : b;
 
 function plotStats(stats, add) {
-  add(0.5, 0.2, 0x000000);
+  add(0.5, stats.no_signal ? 2.0 : 0.2, 0x000000);
 
   add(stats.energy, stats.is_beat ? 3.0 : 0.8, colors[0]);
   add(stats.short, 0.5, colors[1]);
@@ -175,6 +215,20 @@ function plotStats(stats, add) {
 
   add(stats.confidence, stats.confidence, colors[3]);
   add(stats.phase_error, 0.5, colors[4]);
+
+  add(stats.kick, stats.is_kick ? 2.0 : 0.3, colors[5]);
+  add(stats.snare, stats.is_snare ? 2.0 : 0.3, colors[7]);
+  add(stats.hihat, stats.is_hihat ? 2.0 : 0.3, colors[8]);
+
+  // Downbeats and phrase starts along the bottom.
+  if (stats.beat_in_bar == 0) {
+    add(0.05, 0.5 + 2.0 * stats.downbeat_confidence, colors[6]);
+  }
+  add(0.1 + 0.1 * stats.phrase_8_bar / 8, 0.3, colors[7]);
+
+  // Steady, breakdown, build-up and drop along the top.
+  add(0.8 + 0.05 * stats.section, 1.0, colors[stats.section]);
+  add(0.8 + 0.15 * stats.build_up_progress, 0.3, colors[2]);
 }
 
 initializeGraphics(floatsToEnergyStats, plotStats);